@@ -43,6 +43,7 @@ struct Turnstile {
     riders: u32,
 }
 
+#[allow(clippy::derivable_impls)]
 impl Default for Turnstile {
     fn default() -> Self {
         Self {
@@ -64,17 +65,17 @@ impl Turnstile {
 fn main() {
     let mut machine = create_turnstile_fsm();
 
-    machine.start();
+    machine.start().unwrap();
 
     loop {
         let action = input!("add a coin with (c) and push with (p): ");
 
         match action.trim().to_lowercase().parse() {
             Ok('c') => {
-                machine.fire(Coin);
+                machine.fire(Coin).unwrap();
             }
             Ok('p') => {
-                machine.fire(Push);
+                machine.fire(Push).unwrap();
             }
             _ => continue,
         };
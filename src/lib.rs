@@ -24,7 +24,17 @@ pub(crate) mod machine;
 
 pub use machine::active;
 pub use machine::builder;
+pub use machine::error;
+#[cfg(feature = "loader")]
+pub use machine::loader;
 pub use machine::passive;
+pub use machine::plantuml;
+pub use machine::queue;
+#[cfg(any(feature = "loader", feature = "scxml"))]
+pub use machine::registry;
+pub use machine::scxml;
+#[cfg(feature = "testing")]
+pub use machine::testing;
 
 #[cfg(test)]
 pub mod tests {
@@ -49,6 +59,7 @@ pub mod tests {
     }
 
     #[test]
+    #[allow(clippy::bool_assert_comparison)]
     pub fn test_readme_example() {
         // Initial state: closed
         let builder = StateMachineBuilder::create(Closed, DoorModel { door_open: false })
@@ -69,11 +80,11 @@ pub mod tests {
             .goto(Closed);
 
         let mut machine = builder.build_passive();
-        machine.start();
+        machine.start().unwrap();
 
         assert_eq!(machine.model().door_open, false);
 
-        machine.fire(OpenDoor);
+        machine.fire(OpenDoor).unwrap();
 
         assert_eq!(machine.model().door_open, true);
     }
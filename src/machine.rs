@@ -22,22 +22,33 @@
 
 pub mod active;
 pub mod builder;
+pub mod error;
+#[cfg(feature = "loader")]
+pub mod loader;
 pub mod passive;
+pub mod plantuml;
+pub mod queue;
+#[cfg(any(feature = "loader", feature = "scxml"))]
+pub mod registry;
+pub mod scxml;
+#[cfg(feature = "testing")]
+pub mod testing;
 
 #[cfg(test)]
 mod tests {
     use super::builder::StateMachineBuilder;
+    use super::error::{HandlerKind, HandlerPanic};
     use Events::{AddEgg, CloseBasket, OpenBasket, TakeEgg};
     use States::{BasketClosed, BasketOpened};
     use std::sync::{Arc, Mutex};
 
-    #[derive(Eq, PartialEq, Copy, Clone, Hash)]
+    #[derive(Eq, PartialEq, Copy, Clone, Hash, Debug)]
     enum States {
         BasketClosed,
         BasketOpened,
     }
 
-    #[derive(Eq, PartialEq, Copy, Clone, Hash)]
+    #[derive(Eq, PartialEq, Copy, Clone, Hash, Debug)]
     enum Events {
         OpenBasket,
         AddEgg,
@@ -85,36 +96,36 @@ mod tests {
             .goto(BasketClosed);
 
         let mut machine = open_state_builder.build_passive();
-        machine.start();
+        machine.start().unwrap();
 
         // Initial state -- closed basket with a dozen eggs
         assert!(!machine.model().is_open);
         assert_eq!(machine.model().eggs, 12);
 
         // Try to add egg before it's open -- no change to egg count
-        machine.fire(AddEgg);
+        machine.fire(AddEgg).unwrap();
 
         assert!(!machine.model().is_open);
         assert_eq!(machine.model().eggs, 12);
 
         // Open basket
-        machine.fire(OpenBasket);
+        machine.fire(OpenBasket).unwrap();
 
         assert!(machine.model().is_open);
 
         // Add egg to open basket
-        machine.fire(AddEgg);
+        machine.fire(AddEgg).unwrap();
 
         assert_eq!(machine.model().eggs, 13);
 
         // Remove two eggs from open basket
-        machine.fire(TakeEgg);
-        machine.fire(TakeEgg);
+        machine.fire(TakeEgg).unwrap();
+        machine.fire(TakeEgg).unwrap();
 
         assert_eq!(machine.model().eggs, 11);
 
         // Close basket, restore egg count to one dozen upon exit
-        machine.fire(CloseBasket);
+        machine.fire(CloseBasket).unwrap();
 
         assert_eq!(machine.model().eggs, 12);
     }
@@ -164,7 +175,7 @@ mod tests {
             .goto(BasketClosed);
 
         let mut machine = open_state_builder.build_passive();
-        machine.start();
+        machine.start().unwrap();
 
         {
             // Initial state -- closed basket with a dozen eggs
@@ -174,7 +185,7 @@ mod tests {
         }
 
         // Try to add egg before it's open -- no change to egg count
-        machine.fire(AddEgg);
+        machine.fire(AddEgg).unwrap();
 
         {
             let model = shared_model.lock().unwrap();
@@ -183,7 +194,7 @@ mod tests {
         }
 
         // Open basket
-        machine.fire(OpenBasket);
+        machine.fire(OpenBasket).unwrap();
 
         {
             let model = shared_model.lock().unwrap();
@@ -191,7 +202,7 @@ mod tests {
         }
 
         // Add egg to open basket
-        machine.fire(AddEgg);
+        machine.fire(AddEgg).unwrap();
 
         {
             let model = shared_model.lock().unwrap();
@@ -199,8 +210,8 @@ mod tests {
         }
 
         // Remove two eggs from open basket
-        machine.fire(TakeEgg);
-        machine.fire(TakeEgg);
+        machine.fire(TakeEgg).unwrap();
+        machine.fire(TakeEgg).unwrap();
 
         {
             let model = shared_model.lock().unwrap();
@@ -208,11 +219,495 @@ mod tests {
         }
 
         // Close basket, restore egg count to one dozen upon exit
-        machine.fire(CloseBasket);
+        machine.fire(CloseBasket).unwrap();
 
         {
             let model = shared_model.lock().unwrap();
             assert_eq!(model.eggs, 12);
         }
     }
+
+    #[test]
+    fn test_transition_context() {
+        let builder = StateMachineBuilder::create(BasketClosed, Vec::new())
+            .on_enter_with(|ctx, log: &mut Vec<&str>| match ctx.from {
+                None => log.push("start closed"),
+                Some(_) => log.push("enter closed"),
+            })
+            .on(OpenBasket, || {})
+            .goto(BasketOpened)
+            .in_state(BasketOpened)
+            .on_enter_with(|ctx, log: &mut Vec<&str>| {
+                assert_eq!(ctx.from, Some(BasketClosed));
+                assert_eq!(ctx.to, Some(BasketOpened));
+                assert_eq!(ctx.event, Some(OpenBasket));
+                log.push("enter opened");
+            })
+            .on_leave_with(|ctx, log: &mut Vec<&str>| {
+                assert_eq!(ctx.from, Some(BasketOpened));
+                assert_eq!(ctx.to, Some(BasketClosed));
+                assert_eq!(ctx.event, Some(CloseBasket));
+                log.push("leave opened");
+            })
+            .on(CloseBasket, || {})
+            .goto(BasketClosed);
+
+        let mut machine = builder.build_passive();
+        machine.start().unwrap();
+        machine.fire(OpenBasket).unwrap();
+        machine.fire(CloseBasket).unwrap();
+
+        assert_eq!(
+            machine.model(),
+            &vec![
+                "start closed",
+                "enter opened",
+                "leave opened",
+                "enter closed"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_failed_handler_aborts_transition() {
+        let builder = StateMachineBuilder::create(BasketClosed, 0)
+            .on(OpenBasket, || {})
+            .goto(BasketOpened)
+            .in_state(BasketOpened)
+            .try_on_mut(TakeEgg, |eggs: &mut u32| {
+                *eggs += 1;
+                Ok::<(), &str>(())
+            })
+            .try_on_mut(TakeEgg, |_| Err("basket is empty"))
+            .on_mut(TakeEgg, |eggs: &mut u32| *eggs += 100)
+            .goto(BasketClosed);
+
+        let mut machine = builder.build_passive();
+        machine.start().unwrap();
+        machine.fire(OpenBasket).unwrap();
+
+        let error = machine.fire(TakeEgg).unwrap_err();
+
+        assert_eq!(error.state, BasketOpened);
+        assert_eq!(error.event, Some(TakeEgg));
+        assert_eq!(error.kind, HandlerKind::Event);
+        assert_eq!(error.index, 1);
+        assert_eq!(error.cause.to_string(), "basket is empty");
+
+        // The handler before the failure ran, the one after didn't, and the state didn't change
+        assert_eq!(*machine.model(), 1);
+        assert_eq!(*machine.current_state(), BasketOpened);
+    }
+
+    #[test]
+    fn test_failed_handler_goes_to_error_state() {
+        let builder = StateMachineBuilder::create(BasketClosed, false)
+            .try_on_leave_mut(|_| Err("lid is stuck"))
+            .on(OpenBasket, || {})
+            .goto(BasketOpened)
+            .on_error()
+            .goto(BasketOpened)
+            .in_state(BasketOpened)
+            .on_enter_mut(|entered: &mut bool| *entered = true);
+
+        let mut machine = builder.build_passive();
+        machine.start().unwrap();
+
+        let error = machine.fire(OpenBasket).unwrap_err();
+
+        assert_eq!(error.kind, HandlerKind::Leave);
+        assert_eq!(*machine.current_state(), BasketOpened);
+        assert!(*machine.model());
+    }
+
+    #[test]
+    fn test_failed_start() {
+        let mut machine = StateMachineBuilder::<_, _, Events>::create(BasketClosed, ())
+            .try_on_enter_mut(|_| Err("lid is stuck"))
+            .build_passive();
+
+        let error = machine.start().unwrap_err();
+
+        assert_eq!(error.kind, HandlerKind::Enter);
+        assert!(!machine.is_running());
+
+        // A machine that enters its error state instead counts as running
+        let mut machine = StateMachineBuilder::<_, _, Events>::create(BasketClosed, 0)
+            .try_on_enter_mut(|_| Err("lid is stuck"))
+            .on_error()
+            .goto(BasketOpened)
+            .in_state(BasketOpened)
+            .on_enter_mut(|entered: &mut u32| *entered += 1)
+            .build_passive();
+
+        let error = machine.start().unwrap_err();
+
+        assert_eq!(error.kind, HandlerKind::Enter);
+        assert_eq!(*machine.current_state(), BasketOpened);
+        assert!(machine.is_running());
+
+        // Starting again doesn't enter the error state a second time
+        machine.start().unwrap();
+        assert_eq!(*machine.model(), 1);
+    }
+
+    #[test]
+    fn test_caught_panic_reported_as_failure() {
+        let failures = Arc::new(Mutex::new(Vec::new()));
+        let reported = Arc::clone(&failures);
+
+        let builder = StateMachineBuilder::create(BasketClosed, ())
+            .catch_panics()
+            .on_failure(move |error| reported.lock().unwrap().push(error.to_string()))
+            .on(OpenBasket, || panic!("lid fell off"))
+            .goto(BasketOpened);
+
+        let mut machine = builder.build_passive();
+        machine.start().unwrap();
+
+        let error = machine.fire(OpenBasket).unwrap_err();
+        let cause = error.cause.downcast_ref::<HandlerPanic>().unwrap();
+
+        assert_eq!(cause.message.as_deref(), Some("lid fell off"));
+        assert_eq!(*machine.current_state(), BasketClosed);
+        assert_eq!(
+            *failures.lock().unwrap(),
+            vec!["event handler #0 failed: handler panicked: lid fell off"]
+        );
+    }
+
+    #[test]
+    fn test_any_state() {
+        let builder = StateMachineBuilder::create(BasketClosed, 0)
+            .on(OpenBasket, || {})
+            .goto(BasketOpened)
+            .in_state(BasketOpened)
+            .on_mut(AddEgg, |eggs: &mut u32| *eggs += 1)
+            .in_any_state()
+            .on_mut(AddEgg, |eggs: &mut u32| *eggs += 100)
+            .on(CloseBasket, || {})
+            .goto(BasketClosed);
+
+        let mut machine = builder.build_passive();
+        machine.start().unwrap();
+
+        // The closed basket only has the definition for any state
+        machine.fire(AddEgg).unwrap();
+        assert_eq!(*machine.model(), 100);
+
+        // The open basket's own definition takes precedence
+        machine.fire(OpenBasket).unwrap();
+        machine.fire(AddEgg).unwrap();
+        assert_eq!(*machine.model(), 101);
+
+        machine.fire(CloseBasket).unwrap();
+        assert_eq!(*machine.current_state(), BasketClosed);
+    }
+
+    #[test]
+    fn test_unhandled_events() {
+        #[derive(Default)]
+        struct Unhandled {
+            in_closed: u32,
+            anywhere: u32,
+        }
+
+        let builder = StateMachineBuilder::create(BasketClosed, Unhandled::default())
+            .on_unhandled(|state, event, model: &mut Unhandled| {
+                assert_eq!(*state, BasketClosed);
+                assert_eq!(*event, AddEgg);
+                model.in_closed += 1;
+            })
+            .on(OpenBasket, || {})
+            .goto(BasketOpened)
+            .in_any_state()
+            .on_unhandled(|_, _, model: &mut Unhandled| model.anywhere += 1);
+
+        let mut machine = builder.build_passive();
+        machine.start().unwrap();
+
+        machine.fire(AddEgg).unwrap();
+        machine.fire(OpenBasket).unwrap();
+        machine.fire(TakeEgg).unwrap();
+        machine.fire(OpenBasket).unwrap();
+
+        assert_eq!(machine.model().in_closed, 1);
+        assert_eq!(machine.model().anywhere, 2);
+    }
+
+    #[test]
+    fn test_guarded_transition() {
+        let builder = StateMachineBuilder::create(BasketClosed, 0)
+            .on_mut(OpenBasket, |attempts: &mut u32| *attempts += 1)
+            .goto_if(BasketOpened, |attempts: &u32| *attempts >= 3);
+
+        let mut machine = builder.build_passive();
+        machine.start().unwrap();
+
+        // The lid is stuck until the third try
+        machine.fire(OpenBasket).unwrap();
+        machine.fire(OpenBasket).unwrap();
+        assert_eq!(*machine.current_state(), BasketClosed);
+
+        machine.fire(OpenBasket).unwrap();
+        assert_eq!(*machine.current_state(), BasketOpened);
+    }
+
+    #[test]
+    fn test_named_handlers() {
+        let machine = StateMachineBuilder::create(BasketClosed, 0)
+            .on_enter_named("count_closings", |closings: &mut u32| *closings += 1)
+            .on_leave(|| {})
+            .on(OpenBasket, || {})
+            .label("creak")
+            .goto_if(BasketOpened, |_: &u32| true)
+            .label("lid_free")
+            .in_state(BasketOpened)
+            .on_leave_named("check_eggs", |_: &mut u32| {})
+            .try_on_mut(TakeEgg, |_| Ok::<(), &str>(()))
+            .label("take_egg")
+            .in_any_state()
+            .on_named(CloseBasket, "shut_lid", |_: &mut u32| {})
+            .goto(BasketClosed)
+            .on_unhandled(|_, _, _| {})
+            .label("ignore")
+            .build_passive();
+
+        assert_eq!(
+            machine.enter_handler_names(&BasketClosed),
+            [Some("count_closings")]
+        );
+        assert_eq!(machine.leave_handler_names(&BasketClosed), [None]);
+        assert_eq!(
+            machine.leave_handler_names(&BasketOpened),
+            [Some("check_eggs")]
+        );
+        assert_eq!(
+            machine.event_handler_names(&BasketClosed, &OpenBasket),
+            [Some("creak")]
+        );
+        assert_eq!(
+            machine.guard_name(&BasketClosed, &OpenBasket),
+            Some(Some("lid_free"))
+        );
+        assert_eq!(
+            machine.event_handler_names(&BasketOpened, &CloseBasket),
+            [Some("shut_lid")]
+        );
+        assert_eq!(machine.guard_name(&BasketOpened, &CloseBasket), None);
+        assert_eq!(
+            machine.event_handler_names(&BasketOpened, &TakeEgg),
+            [Some("take_egg")]
+        );
+        assert_eq!(
+            machine.unhandled_handler_names(&BasketClosed),
+            [Some("ignore")]
+        );
+
+        // States and events are listed in order of their Debug output
+        let dump = format!("{machine:?}");
+        assert!(dump.contains(r#"enter: [Some("count_closings")]"#));
+        assert!(dump.contains(r#"guard: Some(Some("lid_free"))"#));
+        assert!(
+            dump.find("BasketClosed: State").unwrap() < dump.find("BasketOpened: State").unwrap()
+        );
+        assert!(dump.find("CloseBasket: Event").unwrap() < dump.find("TakeEgg: Event").unwrap());
+    }
+
+    #[test]
+    fn test_introspection() {
+        let machine = StateMachineBuilder::create(BasketClosed, ())
+            .on(OpenBasket, || {})
+            .goto(BasketOpened)
+            .in_state(BasketOpened)
+            .on(AddEgg, || {})
+            .in_any_state()
+            .on(CloseBasket, || {})
+            .goto(BasketClosed)
+            .build_passive();
+
+        assert_eq!(*machine.initial_state(), BasketClosed);
+        assert_eq!(machine.states().len(), 2);
+        assert_eq!(machine.events().len(), 3);
+        assert_eq!(machine.handled_events(&BasketClosed).len(), 2);
+
+        let mut transitions: Vec<_> = machine.transitions().collect();
+        transitions.sort_by_key(|(from, event, _)| (**from as u8, **event as u8));
+        assert_eq!(
+            transitions,
+            [
+                (&BasketClosed, &OpenBasket, &BasketOpened),
+                (&BasketClosed, &CloseBasket, &BasketClosed),
+                (&BasketOpened, &CloseBasket, &BasketClosed),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_permitted_events() {
+        let mut machine = StateMachineBuilder::create(BasketClosed, 0)
+            .on(OpenBasket, || {})
+            .goto(BasketOpened)
+            .in_state(BasketOpened)
+            .on_mut(AddEgg, |eggs: &mut u32| *eggs += 1)
+            .on(CloseBasket, || {})
+            .goto_if(BasketClosed, |eggs: &u32| *eggs > 0)
+            .build_passive();
+        machine.start().unwrap();
+
+        assert_eq!(machine.permitted_events(), [&OpenBasket]);
+        assert!(!machine.can_fire(&AddEgg));
+
+        // The event's handler runs whatever the guard says, so an empty basket counts as closable
+        machine.fire(OpenBasket).unwrap();
+        assert!(machine.can_fire(&AddEgg));
+        assert!(machine.can_fire(&CloseBasket));
+        assert!(!machine.can_fire(&TakeEgg));
+
+        let mut permitted = machine.permitted_events();
+        permitted.sort_by_key(|event| format!("{:?}", event));
+        assert_eq!(permitted, [&AddEgg, &CloseBasket]);
+
+        // Without handlers, as a loaded definition may have, only the guard decides
+        let guard = |eggs: &u32| *eggs > 0;
+        machine.add_transition(
+            TakeEgg,
+            Some(BasketOpened),
+            BasketClosed,
+            Some(Box::new(guard)),
+        );
+        assert!(!machine.can_fire(&TakeEgg));
+        machine.fire(AddEgg).unwrap();
+        assert!(machine.can_fire(&TakeEgg));
+    }
+
+    #[test]
+    fn test_peek() {
+        let mut machine = StateMachineBuilder::create(BasketClosed, 0)
+            .on_leave(|| {})
+            .label("unlatch")
+            .on(OpenBasket, || {})
+            .goto(BasketOpened)
+            .in_state(BasketOpened)
+            .on_enter_named("count_openings", |openings: &mut u32| *openings += 1)
+            .on_mut(CloseBasket, |openings: &mut u32| *openings += 10)
+            .label("tally")
+            .goto_if(BasketClosed, |openings: &u32| *openings > 10)
+            .label("enough_openings")
+            .on_unhandled(|_: &_, _: &_, _: &mut u32| {})
+            .label("shrug")
+            .build_passive();
+        machine.start().unwrap();
+
+        assert!(machine.peek(&AddEgg).is_none());
+
+        let plan = machine.peek(&OpenBasket).unwrap();
+        assert_eq!(plan.to, Some(&BasketOpened));
+        assert_eq!(plan.guard, None);
+        assert_eq!(plan.event_handlers, [None]);
+        assert_eq!(plan.leave_handlers, [Some("unlatch")]);
+        assert_eq!(plan.enter_handlers, [Some("count_openings")]);
+
+        // Peeking changes nothing
+        assert_eq!(*machine.current_state(), BasketClosed);
+        assert_eq!(*machine.model(), 0);
+
+        // The guard would only pass once the event's handler has run, so the move is reported
+        // along with it
+        machine.fire(OpenBasket).unwrap();
+        let plan = machine.peek(&CloseBasket).unwrap();
+        assert_eq!(plan.to, Some(&BasketClosed));
+        assert_eq!(plan.guard, Some(Some("enough_openings")));
+        assert_eq!(plan.event_handlers, [Some("tally")]);
+        assert!(plan.unhandled_handlers.is_empty());
+        machine.fire(CloseBasket).unwrap();
+        assert_eq!(*machine.current_state(), BasketClosed);
+
+        machine.fire(OpenBasket).unwrap();
+        let plan = machine.peek(&TakeEgg).unwrap();
+        assert_eq!(plan.to, None);
+        assert!(plan.event_handlers.is_empty());
+        assert_eq!(plan.unhandled_handlers, [Some("shrug")]);
+    }
+
+    #[test]
+    fn test_stop_and_reset() {
+        let mut machine = StateMachineBuilder::create(BasketClosed, Vec::new())
+            .on_enter_mut(|log: &mut Vec<&str>| log.push("enter closed"))
+            .on(OpenBasket, || {})
+            .goto(BasketOpened)
+            .in_state(BasketOpened)
+            .on_leave_mut(|log| log.push("leave opened"))
+            .build_passive();
+
+        machine.start().unwrap();
+        machine.fire(OpenBasket).unwrap();
+        machine.stop().unwrap();
+
+        assert!(!machine.is_running());
+        assert_eq!(*machine.model(), ["enter closed", "leave opened"]);
+
+        machine.reset_with(|log| log.clear()).unwrap();
+        assert_eq!(*machine.current_state(), BasketClosed);
+
+        machine.start().unwrap();
+        assert_eq!(*machine.model(), ["enter closed"]);
+    }
+
+    #[test]
+    fn test_failed_stop_and_reset() {
+        let mut machine = StateMachineBuilder::create(BasketClosed, 0)
+            .on(OpenBasket, || {})
+            .goto(BasketOpened)
+            .in_state(BasketOpened)
+            .try_on_leave_with(|ctx, eggs: &mut u32| match ctx.to {
+                // Stopping is refused while there are eggs inside
+                None if *eggs > 0 => Err("the basket isn't empty"),
+                _ => Ok(()),
+            })
+            .on_mut(AddEgg, |eggs| *eggs += 1)
+            .build_passive();
+
+        machine.start().unwrap();
+        machine.fire(OpenBasket).unwrap();
+        machine.fire(AddEgg).unwrap();
+
+        let error = machine.stop().unwrap_err();
+        assert_eq!(error.kind, HandlerKind::Leave);
+        assert_eq!(error.event, None);
+        assert!(machine.is_running());
+
+        // Nothing is reset unless the machine stops
+        assert!(machine.reset_with(|eggs| *eggs = 0).is_err());
+        assert!(machine.is_running());
+        assert_eq!(*machine.current_state(), BasketOpened);
+        assert_eq!(*machine.model(), 1);
+
+        *machine.model_mut() = 0;
+        machine.reset().unwrap();
+        assert!(!machine.is_running());
+        assert_eq!(*machine.current_state(), BasketClosed);
+    }
+
+    #[test]
+    fn test_runtime_defined_states() {
+        let workflow = [("draft", "submit", "review"), ("review", "approve", "done")];
+
+        let mut builder = StateMachineBuilder::create(String::from("draft"), Vec::new());
+        for (from, event, to) in workflow {
+            builder = builder
+                .in_state(from.to_string())
+                .on_enter_with(|ctx, log: &mut Vec<String>| log.push(ctx.to.clone().unwrap()))
+                .on(event.to_string(), || {})
+                .goto(to.to_string());
+        }
+
+        let mut machine = builder.build_passive();
+        machine.start().unwrap();
+        machine.fire("submit".to_string()).unwrap();
+        machine.fire("approve".to_string()).unwrap();
+
+        assert_eq!(machine.current_state(), "done");
+        assert_eq!(*machine.model(), vec!["draft", "review"]);
+    }
 }
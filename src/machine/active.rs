@@ -21,107 +21,691 @@
 // SOFTWARE.
 
 use crate::active::ActiveMachineEvent::*;
-use crate::passive::PassiveStateMachine;
+use crate::error::{FireError, HandlerError, HandlerKind, StepError};
+use crate::passive::{Handled, PassiveStateMachine, run_handler};
+use crate::queue::{self, Overflow, Priority, QueueConfig, QueueStats, TryRecvError};
 use std::hash::Hash;
-use std::sync::{Arc, RwLock, mpsc};
+use std::sync::{Arc, Mutex, RwLock, mpsc};
 use std::thread;
 use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
 
-enum ActiveMachineEvent<T: Eq + Hash + Copy> {
+type Tick<TState, TModel> = dyn Fn(&TState, &TModel) -> Option<TState> + 'static + Send + Sync;
+type Reply<TState, TEvent> = mpsc::Sender<FireOutcome<TState, TEvent>>;
+type ModelReset<TModel> = dyn FnOnce(&mut TModel) + Send;
+type Commands<TState, TModel, TEvent> = queue::Sender<ActiveMachineEvent<TState, TModel, TEvent>>;
+type Subscribers<TState, TEvent> = Arc<Mutex<Vec<mpsc::Sender<TransitionEvent<TState, TEvent>>>>>;
+pub(crate) type Stepped<TState, TEvent> =
+    Result<Option<Result<(), HandlerError<TState, TEvent>>>, StepError>;
+
+enum ActiveMachineEvent<TState, TModel, TEvent> {
     Start,
     Stop,
-    ExternalEvent(T),
+    Pause,
+    Resume,
+    Reset(Option<Box<ModelReset<TModel>>>),
+    // Carries a channel to send the outcome to if someone is waiting for it
+    ExternalEvent(TEvent, Option<Reply<TState, TEvent>>),
+}
+
+/// A transition published to subscribers of an active machine
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct TransitionEvent<TState, TEvent> {
+    pub from: TState,
+    pub to: TState,
+    /// The event that caused the transition, or `None` if it came from a tick or a reset
+    pub event: Option<TEvent>,
+}
+
+/// What happened to an event fired with `fire_and_wait`
+#[derive(Debug)]
+pub enum FireOutcome<TState, TEvent> {
+    /// The event moved the machine from one state to another, which may be the same state
+    Transitioned { from: TState, to: TState },
+    /// The event's handlers ran, but the machine stayed in its state
+    Stayed { state: TState },
+    /// The state doesn't handle the event, so only `on_unhandled` handlers ran
+    Unhandled { state: TState },
+    /// The queue was full, so the event was discarded without being handled
+    Dropped,
+    /// The machine is paused and was built to reject events until it resumes
+    Paused,
+    /// A handler failed, leaving the machine in `state`
+    Failed {
+        error: HandlerError<TState, TEvent>,
+        state: TState,
+    },
+    /// The event wasn't handled in time. It stays queued and will still be handled.
+    TimedOut,
+    /// The machine stopped before handling the event
+    Stopped,
+    /// The machine wasn't running, since its start failed, so the event was discarded
+    NotRunning,
+    /// The machine is stepped by hand, so nothing could handle the event while waiting for it.
+    /// The event wasn't queued; use `fire` and `step` instead.
+    Manual,
+}
+
+/// Whether the machine loop runs on its own thread or is stepped by hand
+enum Driver<TState, TModel, TEvent>
+where
+    TState: Eq + Hash + Clone,
+    TEvent: Eq + Hash + Clone,
+{
+    Thread(JoinHandle<()>),
+    Manual(Mutex<Worker<TState, TModel, TEvent>>),
+}
+
+/// Runs the commands sent to an active machine, ticking it when there are none
+struct Worker<TState, TModel, TEvent>
+where
+    TState: Eq + Hash + Clone,
+    TEvent: Eq + Hash + Clone,
+{
+    machine: Arc<RwLock<PassiveStateMachine<TState, TModel, TEvent>>>,
+    rx: queue::Receiver<ActiveMachineEvent<TState, TModel, TEvent>>,
+    tick: Box<Tick<TState, TModel>>,
+    subscribers: Subscribers<TState, TEvent>,
+    // While paused only urgent commands are taken off the queue, and the machine isn't ticked
+    paused: bool,
 }
 
 pub struct ActiveStateMachine<TState, TModel = (), TEvent = ()>
 where
-    TState: Eq + Hash + Copy,
-    TEvent: Eq + Hash + Copy,
+    TState: Eq + Hash + Clone,
+    TEvent: Eq + Hash + Clone,
 {
-    internal_state: Arc<RwLock<PassiveStateMachine<TState, TModel, TEvent>>>,
-    machine_loop: JoinHandle<()>,
-    tx: mpsc::Sender<ActiveMachineEvent<TEvent>>,
+    handle: MachineHandle<TState, TModel, TEvent>,
+    // Only taken when the machine is stopped, which consumes it
+    driver: Option<Driver<TState, TModel, TEvent>>,
+}
+
+/// A cloneable handle to an active machine, for firing events into it, reading it and subscribing
+/// to it from other threads. Only the machine's owner can start and stop it.
+pub struct MachineHandle<TState, TModel = (), TEvent = ()>
+where
+    TState: Eq + Hash + Clone,
+    TEvent: Eq + Hash + Clone,
+{
+    machine: Arc<RwLock<PassiveStateMachine<TState, TModel, TEvent>>>,
+    tx: Commands<TState, TModel, TEvent>,
+    subscribers: Subscribers<TState, TEvent>,
+    manual: bool,
+}
+
+impl<TState, TModel, TEvent> Worker<TState, TModel, TEvent>
+where
+    TState: Eq + Hash + Clone,
+    TEvent: Eq + Hash + Clone,
+{
+    /// Handle one command, or tick if there are none. Returns `None` once the machine has stopped.
+    fn step(&mut self) -> Option<Result<(), HandlerError<TState, TEvent>>> {
+        let received = if self.paused {
+            self.rx.try_recv_command()
+        } else {
+            self.rx.try_recv()
+        };
+
+        match received {
+            Ok(Start) => {
+                let mut machine = self.machine.write().unwrap();
+                Some(machine.start())
+            }
+            Ok(ExternalEvent(_, reply)) if !self.machine.read().unwrap().is_running() => {
+                if let Some(reply) = reply {
+                    let _ = reply.send(FireOutcome::NotRunning);
+                }
+                Some(Ok(()))
+            }
+            Ok(ExternalEvent(event, None)) => {
+                let mut machine = self.machine.write().unwrap();
+                Some(machine.fire(event))
+            }
+            Ok(ExternalEvent(event, Some(reply))) => {
+                let mut machine = self.machine.write().unwrap();
+                let handled = machine.handle(event);
+                let state = machine.current_state().clone();
+
+                let outcome = match handled {
+                    Ok(Handled::Transitioned { from }) => {
+                        FireOutcome::Transitioned { from, to: state }
+                    }
+                    Ok(Handled::Stayed) => FireOutcome::Stayed { state },
+                    Ok(Handled::Unhandled) => FireOutcome::Unhandled { state },
+                    Err(error) => FireOutcome::Failed { error, state },
+                };
+
+                // The failure, if any, goes to whoever is waiting, who may have given up
+                let _ = reply.send(outcome);
+                Some(Ok(()))
+            }
+            Ok(Stop) => None,
+            Ok(Reset(reset_model)) => {
+                let mut machine = self.machine.write().unwrap();
+                let running = machine.is_running();
+                let reset = match reset_model {
+                    Some(reset_model) => machine.reset_with(reset_model),
+                    None => machine.reset(),
+                };
+
+                // A machine that was running carries on from its initial state
+                Some(reset.and_then(|_| if running { machine.start() } else { Ok(()) }))
+            }
+            Ok(Pause) => {
+                self.paused = true;
+                Some(Ok(()))
+            }
+            Ok(Resume) => {
+                self.paused = false;
+                Some(Ok(()))
+            }
+            Err(TryRecvError::Empty) if self.paused => Some(Ok(())),
+            Err(TryRecvError::Empty) => {
+                let mut machine = self.machine.write().unwrap();
+                if !machine.is_running() {
+                    return Some(Ok(()));
+                }
+
+                let next = run_handler(machine.catches_panics(), || {
+                    Ok((self.tick)(machine.current_state(), machine.model()))
+                });
+
+                match next {
+                    Ok(Some(state)) => Some(machine.goto(state, None)),
+                    Ok(None) => Some(Ok(())),
+                    Err(cause) => Some(Err(machine.fail(HandlerKind::Tick, 0, cause, None))),
+                }
+            }
+            Err(TryRecvError::Disconnected) => None,
+        }
+    }
 }
 
 impl<TState, TModel, TEvent> ActiveStateMachine<TState, TModel, TEvent>
 where
-    TEvent: Eq + Hash + Copy + Sync + Send + 'static,
-    TState: Eq + Hash + Copy + Sync + Send + 'static,
+    TEvent: Eq + Hash + Clone + Sync + Send + 'static,
+    TState: Eq + Hash + Clone + Sync + Send + 'static,
     TModel: Sync + Send + 'static,
 {
     pub(crate) fn create(
         active_action: impl Fn(&TState, &TModel) -> Option<TState> + 'static + Send + Sync,
         machine: PassiveStateMachine<TState, TModel, TEvent>,
+        queue: QueueConfig,
     ) -> Self {
-        let (tx, rx) = mpsc::channel();
-        let machine = Arc::new(RwLock::new(machine));
-        let internal_state = Arc::clone(&machine);
+        let (tx, mut worker) = Self::worker(active_action, machine, queue);
+        let handle = MachineHandle {
+            machine: Arc::clone(&worker.machine),
+            tx,
+            subscribers: Arc::clone(&worker.subscribers),
+            manual: false,
+        };
 
         let machine_loop = thread::spawn(move || {
-            loop {
-                match rx.try_recv() {
-                    Ok(Start) => {
-                        let mut machine = machine.write().unwrap();
-                        machine.start();
-                    }
-                    Ok(ExternalEvent(event)) => {
-                        let mut machine = machine.write().unwrap();
-                        machine.fire(event);
-                    }
-                    Ok(Stop) => {
-                        return;
-                    }
-                    Err(mpsc::TryRecvError::Empty) => {
-                        let mut machine = machine.write().unwrap();
-                        if let Some(state) = active_action(machine.current_state(), machine.model())
-                        {
-                            machine.goto(state);
-                        }
-                    }
-                    Err(mpsc::TryRecvError::Disconnected) => {
-                        return;
-                    }
-                }
-
+            // Nobody is waiting on the result of a command, so handler failures are only acted
+            // on through the error states and failure handlers given to the builder
+            while worker.step().is_some() {
                 thread::yield_now();
             }
         });
 
         Self {
-            internal_state,
-            machine_loop,
-            tx,
+            handle,
+            driver: Some(Driver::Thread(machine_loop)),
         }
     }
 
-    pub fn fire(&self, event: TEvent) {
-        self.tx.send(ExternalEvent(event)).unwrap();
+    /// Create a machine that only runs when `step` is called
+    pub(crate) fn create_manual(
+        active_action: impl Fn(&TState, &TModel) -> Option<TState> + 'static + Send + Sync,
+        machine: PassiveStateMachine<TState, TModel, TEvent>,
+        mut queue: QueueConfig,
+    ) -> Self {
+        // Whoever would wait for room is usually the one who has to step the machine to make it
+        if queue.overflow == Overflow::Block {
+            queue.overflow = Overflow::Error;
+        }
+
+        let (tx, worker) = Self::worker(active_action, machine, queue);
+
+        Self {
+            handle: MachineHandle {
+                machine: Arc::clone(&worker.machine),
+                tx,
+                subscribers: Arc::clone(&worker.subscribers),
+                manual: true,
+            },
+            driver: Some(Driver::Manual(Mutex::new(worker))),
+        }
+    }
+
+    fn worker(
+        active_action: impl Fn(&TState, &TModel) -> Option<TState> + 'static + Send + Sync,
+        mut machine: PassiveStateMachine<TState, TModel, TEvent>,
+        queue: QueueConfig,
+    ) -> (
+        Commands<TState, TModel, TEvent>,
+        Worker<TState, TModel, TEvent>,
+    ) {
+        let subscribers: Subscribers<TState, TEvent> = Arc::default();
+
+        let publish_to = Arc::clone(&subscribers);
+        machine.add_transition_listener(move |from, to, event| {
+            let transition = TransitionEvent {
+                from: from.clone(),
+                to: to.clone(),
+                event: event.clone(),
+            };
+
+            // Subscribers that have gone away are dropped
+            let mut subscribers = publish_to.lock().unwrap();
+            subscribers.retain(|subscriber| subscriber.send(transition.clone()).is_ok());
+        });
+
+        let (tx, rx) = queue::queue(queue);
+        let worker = Worker {
+            machine: Arc::new(RwLock::new(machine)),
+            rx,
+            tick: Box::new(active_action),
+            subscribers,
+            paused: false,
+        };
+
+        (tx, worker)
+    }
+
+    /// Handle exactly one queued command, or run `active_action` once if none are queued, just
+    /// like one pass of the machine's thread would. Returns false once the machine has stopped.
+    /// Only machines built with `build_active_manual` can be stepped.
+    pub fn step(&self) -> Result<bool, StepError> {
+        Ok(self.step_with_result()?.is_some())
+    }
+
+    /// Like `step`, but with the result of the command or tick
+    pub(crate) fn step_with_result(&self) -> Stepped<TState, TEvent> {
+        match &self.driver {
+            Some(Driver::Manual(worker)) => Ok(worker.lock().unwrap().step()),
+            _ => Err(StepError),
+        }
+    }
+
+    /// A handle for firing events into the machine and reading it from other threads
+    pub fn handle(&self) -> MachineHandle<TState, TModel, TEvent> {
+        self.handle.clone()
+    }
+
+    /// Queue an event for the machine to handle. If the queue is full, the overflow policy given
+    /// to `bounded_queue` decides what happens.
+    pub fn fire(&self, event: TEvent) -> Result<(), FireError<TEvent>> {
+        self.handle.fire(event)
+    }
+
+    /// Like `fire`, but the event is handled before any queued event of a lower priority. It
+    /// never overtakes a command, like `reset`, that was queued before it.
+    pub fn fire_priority(
+        &self,
+        event: TEvent,
+        priority: Priority,
+    ) -> Result<(), FireError<TEvent>> {
+        self.handle.fire_priority(event, priority)
+    }
+
+    /// Fire an event and block until the machine has handled it
+    pub fn fire_and_wait(&self, event: TEvent) -> FireOutcome<TState, TEvent> {
+        self.handle.fire_and_wait(event)
+    }
+
+    /// Like `fire_and_wait`, but gives up waiting after the timeout
+    pub fn fire_with_timeout(
+        &self,
+        event: TEvent,
+        timeout: Duration,
+    ) -> FireOutcome<TState, TEvent> {
+        self.handle.fire_with_timeout(event, timeout)
+    }
+
+    /// The depth and limits of the machine's queue
+    pub fn queue_stats(&self) -> QueueStats {
+        self.handle.queue_stats()
     }
 
     pub fn start(&self) {
-        self.tx.send(Start).unwrap();
+        // A machine that has already stopped can't be started again
+        let _ = self.handle.tx.send_command(Start, true);
     }
 
+    /// Stop once the events queued before the stop are handled, or straight away if the builder
+    /// was given `urgent_stop`. A paused machine is resumed first.
     pub fn stop(self) {
-        self.tx.send(Stop).unwrap();
-        self.machine_loop.join().unwrap();
+        self.resume();
+        let urgent = self.handle.tx.urgent_stop();
+        let _ = self.handle.tx.send_command(Stop, urgent);
+        self.join();
+    }
+
+    /// Handle every event already queued, then stop. Firing an event from a handle fails from
+    /// the moment this is called. A paused machine is resumed first.
+    pub fn stop_after_drain(self) {
+        self.resume();
+        self.handle.tx.close(Stop, false);
+        self.join();
+    }
+
+    /// Stop as soon as the event being handled, if any, is done. The events still queued are
+    /// returned rather than handled, and anyone waiting on them gets `FireOutcome::Stopped`.
+    pub fn stop_now(self) -> Vec<TEvent> {
+        let discarded = self.handle.tx.close(Stop, true);
+        self.join();
+
+        discarded
+            .into_iter()
+            .map(|discarded| match discarded {
+                ExternalEvent(event, reply) => {
+                    if let Some(reply) = reply {
+                        let _ = reply.send(FireOutcome::Stopped);
+                    }
+                    event
+                }
+                _ => unreachable!("only events are discarded"),
+            })
+            .collect()
+    }
+
+    /// Move the machine back to its initial state once the events queued before the reset are
+    /// handled, leaving it as if it had just been started. See `PassiveStateMachine::reset`.
+    pub fn reset(&self) {
+        let _ = self.handle.tx.send_command(Reset(None), false);
+    }
+
+    /// Like `reset`, but also resets the model
+    pub fn reset_with(&self, reset_model: impl FnOnce(&mut TModel) + Send + 'static) {
+        let _ = self
+            .handle
+            .tx
+            .send_command(Reset(Some(Box::new(reset_model))), false);
+    }
+
+    /// Stop ticking the machine and handling events until `resume` is called. Events fired in the
+    /// meantime are queued or rejected, as set with `while_paused` on the builder.
+    pub fn pause(&self) {
+        self.handle.tx.set_paused(true);
+        let _ = self.handle.tx.send_command(Pause, true);
+    }
+
+    /// Carry on after `pause`, handling the events that were queued while paused
+    pub fn resume(&self) {
+        self.handle.tx.set_paused(false);
+        let _ = self.handle.tx.send_command(Resume, true);
+    }
+
+    /// Whether `pause` has been called without a `resume` since
+    pub fn is_paused(&self) -> bool {
+        self.handle.tx.is_paused()
+    }
+
+    fn join(mut self) {
+        // Whatever is queued ahead of the stop is still handled, as it would be by the thread
+        match self.driver.take() {
+            Some(Driver::Thread(machine_loop)) => machine_loop.join().unwrap(),
+            Some(Driver::Manual(worker)) => {
+                let mut worker = worker.into_inner().unwrap();
+                while worker.step().is_some() {}
+            }
+            None => {}
+        }
+    }
+
+    /// Whether the current state handles the event, see `PassiveStateMachine::can_fire`. Events
+    /// that are still queued aren't taken into account.
+    pub fn can_fire(&self, event: &TEvent) -> bool {
+        self.handle.can_fire(event)
+    }
+
+    /// Every event the current state handles, see `PassiveStateMachine::permitted_events`
+    pub fn permitted_events(&self) -> Vec<TEvent> {
+        self.handle.permitted_events()
+    }
+
+    /// Run the function with the underlying machine locked
+    #[cfg(feature = "testing")]
+    pub(crate) fn with_machine<R>(
+        &self,
+        func: impl FnOnce(&mut PassiveStateMachine<TState, TModel, TEvent>) -> R,
+    ) -> R {
+        func(&mut self.handle.machine.write().unwrap())
+    }
+
+    /// Receive every transition the machine makes from now on
+    pub fn subscribe(&self) -> mpsc::Receiver<TransitionEvent<TState, TEvent>> {
+        self.handle.subscribe()
+    }
+
+    /// Block until the machine is in the given state, returning false if it isn't after the
+    /// timeout
+    pub fn wait_for_state(&self, state: &TState, timeout: Duration) -> bool {
+        self.handle.wait_for_state(state, timeout)
     }
 
     pub fn write_model(&mut self, update: impl Fn(&mut TModel) + Send + Sync + 'static) {
-        let mut model = self.internal_state.write().unwrap();
+        let mut model = self.handle.machine.write().unwrap();
         update(model.model_mut())
     }
 
     pub fn read_state<R>(&self, read: impl Fn(&TModel) -> R) -> R {
-        let state = self.internal_state.read().unwrap();
+        self.handle.read_state(read)
+    }
+
+    /// The state the machine is in. It may change as soon as this returns.
+    pub fn current_state(&self) -> TState {
+        self.handle.current_state()
+    }
+
+    /// Read the current state and model together, while the machine can't change them
+    pub fn read<R>(&self, read: impl FnOnce(&TState, &TModel) -> R) -> R {
+        self.handle.read(read)
+    }
+
+    /// Whether the machine has been started and its loop is still going. A handler that panics
+    /// without `catch_panics` ends the loop.
+    pub fn is_running(&self) -> bool {
+        let started = match self.handle.machine.read() {
+            Ok(machine) => machine.is_running(),
+            Err(_) => false,
+        };
+
+        match &self.driver {
+            Some(Driver::Thread(machine_loop)) => started && !machine_loop.is_finished(),
+            _ => started,
+        }
+    }
+}
+
+/// Dropping a machine without stopping it stops it as `stop_now` would, without waiting for the
+/// loop to finish. Handles can't keep the loop going on their own.
+impl<TState, TModel, TEvent> Drop for ActiveStateMachine<TState, TModel, TEvent>
+where
+    TState: Eq + Hash + Clone,
+    TEvent: Eq + Hash + Clone,
+{
+    fn drop(&mut self) {
+        if self.driver.is_some() {
+            self.handle.tx.close(Stop, true);
+        }
+    }
+}
+
+impl<TState, TModel, TEvent> MachineHandle<TState, TModel, TEvent>
+where
+    TState: Eq + Hash + Clone,
+    TEvent: Eq + Hash + Clone,
+{
+    /// Queue an event for the machine to handle. If the queue is full, the overflow policy given
+    /// to `bounded_queue` decides what happens.
+    pub fn fire(&self, event: TEvent) -> Result<(), FireError<TEvent>> {
+        self.fire_priority(event, Priority::Normal)
+    }
+
+    /// Like `fire`, but the event is handled before any queued event of a lower priority. It
+    /// never overtakes a command, like `reset`, that was queued before it.
+    pub fn fire_priority(
+        &self,
+        event: TEvent,
+        priority: Priority,
+    ) -> Result<(), FireError<TEvent>> {
+        // Both dropping policies can discard the event being fired, which isn't an error
+        let dropping = matches!(
+            self.tx.overflow(),
+            Overflow::DropNewest | Overflow::DropOldest
+        );
+        match self.enqueue(event, priority, None) {
+            Err(FireError::Full(_)) if dropping => Ok(()),
+            result => result,
+        }
+    }
+
+    /// Fire an event and block until the machine has handled it
+    pub fn fire_and_wait(&self, event: TEvent) -> FireOutcome<TState, TEvent> {
+        match self.send_and_wait(event) {
+            Ok(outcome) => outcome.recv().unwrap_or(FireOutcome::Stopped),
+            Err(error) => error,
+        }
+    }
+
+    /// Like `fire_and_wait`, but gives up waiting after the timeout
+    pub fn fire_with_timeout(
+        &self,
+        event: TEvent,
+        timeout: Duration,
+    ) -> FireOutcome<TState, TEvent> {
+        let outcome = match self.send_and_wait(event) {
+            Ok(outcome) => outcome,
+            Err(error) => return error,
+        };
+
+        match outcome.recv_timeout(timeout) {
+            Ok(outcome) => outcome,
+            Err(mpsc::RecvTimeoutError::Timeout) => FireOutcome::TimedOut,
+            Err(mpsc::RecvTimeoutError::Disconnected) => FireOutcome::Stopped,
+        }
+    }
+
+    fn send_and_wait(
+        &self,
+        event: TEvent,
+    ) -> Result<mpsc::Receiver<FireOutcome<TState, TEvent>>, FireOutcome<TState, TEvent>> {
+        if self.manual {
+            return Err(FireOutcome::Manual);
+        }
+
+        let (reply, outcome) = mpsc::channel();
+        match self.enqueue(event, Priority::Normal, Some(reply)) {
+            Ok(()) => Ok(outcome),
+            Err(FireError::Full(_)) => Err(FireOutcome::Dropped),
+            Err(FireError::Stopped(_)) => Err(FireOutcome::Stopped),
+            Err(FireError::Paused(_)) => Err(FireOutcome::Paused),
+        }
+    }
+
+    fn enqueue(
+        &self,
+        event: TEvent,
+        priority: Priority,
+        reply: Option<Reply<TState, TEvent>>,
+    ) -> Result<(), FireError<TEvent>> {
+        match self.tx.send(ExternalEvent(event, reply), priority) {
+            Ok(Some(ExternalEvent(_, Some(reply)))) => {
+                // An older event was dropped to make room, so let whoever is waiting on it know
+                let _ = reply.send(FireOutcome::Dropped);
+                Ok(())
+            }
+            Ok(_) => Ok(()),
+            Err(FireError::Full(ExternalEvent(event, _))) => Err(FireError::Full(event)),
+            Err(FireError::Stopped(ExternalEvent(event, _))) => Err(FireError::Stopped(event)),
+            Err(FireError::Paused(ExternalEvent(event, _))) => Err(FireError::Paused(event)),
+            Err(_) => unreachable!("only events are queued with send"),
+        }
+    }
+
+    /// The depth and limits of the machine's queue
+    pub fn queue_stats(&self) -> QueueStats {
+        self.tx.stats()
+    }
+
+    /// Whether the current state handles the event, see `PassiveStateMachine::can_fire`. Events
+    /// that are still queued aren't taken into account.
+    pub fn can_fire(&self, event: &TEvent) -> bool {
+        self.machine.read().unwrap().can_fire(event)
+    }
+
+    /// Every event the current state handles, see `PassiveStateMachine::permitted_events`
+    pub fn permitted_events(&self) -> Vec<TEvent> {
+        let machine = self.machine.read().unwrap();
+        machine.permitted_events().into_iter().cloned().collect()
+    }
+
+    /// Receive every transition the machine makes from now on
+    pub fn subscribe(&self) -> mpsc::Receiver<TransitionEvent<TState, TEvent>> {
+        let (tx, rx) = mpsc::channel();
+        self.subscribers.lock().unwrap().push(tx);
+        rx
+    }
+
+    /// Block until the machine is in the given state, returning false if it isn't after the
+    /// timeout
+    pub fn wait_for_state(&self, state: &TState, timeout: Duration) -> bool {
+        // Subscribe before checking so that a transition in between isn't missed
+        let transitions = self.subscribe();
+        if self.machine.read().unwrap().current_state() == state {
+            return true;
+        }
+
+        let deadline = Instant::now() + timeout;
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            match transitions.recv_timeout(remaining) {
+                Ok(transition) if transition.to == *state => return true,
+                Ok(_) => {}
+                Err(_) => return false,
+            }
+        }
+    }
+
+    pub fn read_state<R>(&self, read: impl Fn(&TModel) -> R) -> R {
+        let state = self.machine.read().unwrap();
         read(state.model())
     }
+
+    /// The state the machine is in. It may change as soon as this returns.
+    pub fn current_state(&self) -> TState {
+        self.machine.read().unwrap().current_state().clone()
+    }
+
+    /// Read the current state and model together, while the machine can't change them
+    pub fn read<R>(&self, read: impl FnOnce(&TState, &TModel) -> R) -> R {
+        let machine = self.machine.read().unwrap();
+        read(machine.current_state(), machine.model())
+    }
+}
+
+impl<TState, TModel, TEvent> Clone for MachineHandle<TState, TModel, TEvent>
+where
+    TState: Eq + Hash + Clone,
+    TEvent: Eq + Hash + Clone,
+{
+    fn clone(&self) -> Self {
+        Self {
+            machine: Arc::clone(&self.machine),
+            tx: self.tx.clone(),
+            subscribers: Arc::clone(&self.subscribers),
+            manual: self.manual,
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::super::builder::StateMachineBuilder;
     use super::*;
+    use crate::queue::WhilePaused;
     use std::time::{Duration, SystemTime};
 
     struct Model<TState> {
@@ -215,4 +799,632 @@ mod tests {
             }
         }
     }
+
+    #[test]
+    fn test_active_state_machine_survives_panic() {
+        const OK: u32 = 1;
+        const FAULTED: u32 = 2;
+        const FAULTED_MARKER: u32 = 999;
+        const EXPLODE: u32 = 7;
+
+        let machine = StateMachineBuilder::create(OK, 0u32)
+            .catch_panics()
+            .on_failure(|_| panic!("failure handler blew up"))
+            .on(EXPLODE, || panic!("handler blew up"))
+            .on_error()
+            .goto(FAULTED)
+            .in_state(FAULTED)
+            .on_enter_mut(|model| *model = FAULTED_MARKER)
+            .build_active(|_, _| None);
+
+        machine.start();
+        let outcome = machine.fire_and_wait(EXPLODE);
+
+        assert!(matches!(
+            outcome,
+            FireOutcome::Failed { state: FAULTED, .. }
+        ));
+        assert_eq!(machine.read_state(|model| *model), FAULTED_MARKER);
+        assert!(machine.is_running());
+
+        machine.stop();
+    }
+
+    #[test]
+    fn test_event_after_failed_start() {
+        let machine = StateMachineBuilder::create(1u32, 0u32)
+            .catch_panics()
+            .try_on_enter_mut(|_| Err("won't start"))
+            .on_mut(5, |fired| *fired += 1)
+            .build_active(|_, _| None);
+
+        machine.start();
+        assert!(matches!(machine.fire_and_wait(5), FireOutcome::NotRunning));
+        assert_eq!(machine.current_state(), 1);
+        assert_eq!(machine.read_state(|fired| *fired), 0);
+
+        machine.stop();
+    }
+
+    #[test]
+    fn test_manual_step() {
+        const IDLE: u32 = 1;
+        const RUNNING: u32 = 2;
+        const DONE: u32 = 3;
+        const GO: u32 = 10;
+
+        let machine = StateMachineBuilder::create(IDLE, 0u32)
+            .on(GO, || {})
+            .goto(RUNNING)
+            .in_state(RUNNING)
+            .on_enter_mut(|runs| *runs += 1)
+            .build_active_manual(|state, _| (*state == RUNNING).then_some(DONE));
+
+        machine.start();
+        machine.fire(GO).unwrap();
+
+        // Nothing happens until the machine is stepped
+        thread::sleep(Duration::from_millis(10));
+        assert_eq!(machine.permitted_events(), [GO]);
+
+        assert_eq!(machine.step(), Ok(true));
+        assert_eq!(machine.step(), Ok(true));
+        assert!(!machine.can_fire(&GO));
+        assert_eq!(machine.read_state(|runs| *runs), 1);
+
+        // With the queue empty, a step ticks the machine
+        assert_eq!(machine.step(), Ok(true));
+        assert!(machine.permitted_events().is_empty());
+
+        machine.stop();
+    }
+
+    #[test]
+    fn test_wrong_driver() {
+        let manual = StateMachineBuilder::create(1u32, ())
+            .on(5, || {})
+            .build_active_manual(|_, _| None);
+        manual.start();
+        assert!(matches!(manual.fire_and_wait(5), FireOutcome::Manual));
+        assert!(matches!(
+            manual.fire_with_timeout(5, Duration::from_millis(10)),
+            FireOutcome::Manual
+        ));
+        assert_eq!(manual.queue_stats().depth, 1);
+        manual.stop();
+
+        let threaded = StateMachineBuilder::create(1u32, ())
+            .on(5, || {})
+            .build_active(|_, _| None);
+        assert_eq!(threaded.step(), Err(StepError));
+        threaded.stop();
+    }
+
+    #[test]
+    fn test_manual_stop_handles_queued_events() {
+        let machine = StateMachineBuilder::create(1u32, 0u32)
+            .on_mut(5, |fired| *fired += 1)
+            .build_active_manual(|_, _| None);
+        let model = Arc::clone(&machine.handle.machine);
+
+        machine.start();
+        machine.fire(5).unwrap();
+        machine.fire(5).unwrap();
+        machine.stop();
+
+        assert_eq!(*model.read().unwrap().model(), 2);
+    }
+
+    #[test]
+    fn test_bounded_queue_rejects_when_full() {
+        let machine = StateMachineBuilder::create(1u32, 0u32)
+            .on_mut(5, |fired| *fired += 1)
+            .bounded_queue(2, Overflow::Error)
+            .build_active_manual(|_, _| None);
+
+        machine.start();
+        machine.fire(5).unwrap();
+        machine.fire(5).unwrap();
+
+        let error = machine.fire(5).unwrap_err();
+        assert!(matches!(error, FireError::Full(5)));
+
+        let stats = machine.queue_stats();
+        assert_eq!(stats.capacity, Some(2));
+        assert_eq!(stats.depth, 3);
+        assert_eq!(stats.dropped, 0);
+
+        for _ in 0..3 {
+            machine.step().unwrap();
+        }
+        assert_eq!(machine.read_state(|fired| *fired), 2);
+        assert_eq!(machine.queue_stats().max_depth, 3);
+    }
+
+    #[test]
+    fn test_manual_machine_doesnt_block() {
+        let machine = StateMachineBuilder::create(1u32, 0u32)
+            .on_mut(5, |fired| *fired += 1)
+            .bounded_queue(1, Overflow::Block)
+            .build_active_manual(|_, _| None);
+
+        machine.start();
+        machine.fire(5).unwrap();
+        assert!(matches!(machine.fire(5), Err(FireError::Full(5))));
+
+        machine.step().unwrap();
+        machine.step().unwrap();
+        machine.fire(5).unwrap();
+        assert_eq!(machine.read_state(|fired| *fired), 1);
+    }
+
+    #[test]
+    fn test_bounded_queue_drops_oldest() {
+        let machine = StateMachineBuilder::create(1u32, Vec::new())
+            .on_mut(5, |handled| handled.push(5))
+            .on_mut(6, |handled| handled.push(6))
+            .on_mut(7, |handled| handled.push(7))
+            .bounded_queue(2, Overflow::DropOldest)
+            .build_active_manual(|_, _| None);
+
+        machine.start();
+        for event in [5, 6, 7] {
+            machine.fire(event).unwrap();
+        }
+        assert_eq!(machine.queue_stats().dropped, 1);
+
+        for _ in 0..3 {
+            machine.step().unwrap();
+        }
+        assert_eq!(machine.read_state(|handled| handled.clone()), [6, 7]);
+    }
+
+    #[test]
+    fn test_fire_priority() {
+        let machine = StateMachineBuilder::create(1u32, Vec::new())
+            .on_mut(5, |handled| handled.push(5))
+            .on_mut(6, |handled| handled.push(6))
+            .on_mut(7, |handled| handled.push(7))
+            .on_mut(8, |handled| handled.push(8))
+            .build_active_manual(|_, _| None);
+        let model = Arc::clone(&machine.handle.machine);
+
+        machine.fire_priority(5, Priority::Low).unwrap();
+        machine.fire(6).unwrap();
+        machine.fire_priority(7, Priority::High).unwrap();
+        machine.fire(8).unwrap();
+        machine.start();
+        machine.stop();
+
+        assert_eq!(*model.read().unwrap().model(), [7, 6, 8, 5]);
+    }
+
+    #[test]
+    fn test_drop_oldest_keeps_higher_priorities() {
+        let machine = StateMachineBuilder::create(1u32, Vec::new())
+            .on_mut(5, |handled| handled.push(5))
+            .on_mut(6, |handled| handled.push(6))
+            .on_mut(7, |handled| handled.push(7))
+            .on_mut(8, |handled| handled.push(8))
+            .bounded_queue(2, Overflow::DropOldest)
+            .build_active_manual(|_, _| None);
+
+        machine.start();
+        machine.fire_priority(5, Priority::High).unwrap();
+        machine.fire_priority(6, Priority::High).unwrap();
+
+        // Only the event being fired is low enough to drop
+        machine.fire(7).unwrap();
+        assert_eq!(machine.queue_stats().dropped, 1);
+
+        machine.fire_priority(8, Priority::High).unwrap();
+        assert_eq!(machine.queue_stats().dropped, 2);
+
+        for _ in 0..3 {
+            machine.step().unwrap();
+        }
+        assert_eq!(machine.read_state(|handled| handled.clone()), [6, 8]);
+    }
+
+    #[test]
+    fn test_events_dont_overtake_commands() {
+        let machine = StateMachineBuilder::create(1u32, Vec::new())
+            .on_mut(5, |handled| handled.push(5))
+            .goto(2)
+            .on_mut(6, |handled| handled.push(6))
+            .build_active_manual(|_, _| None);
+
+        machine.start();
+        machine.fire_priority(5, Priority::Low).unwrap();
+        machine.reset();
+        machine.fire_priority(6, Priority::High).unwrap();
+
+        for _ in 0..4 {
+            machine.step().unwrap();
+        }
+        assert_eq!(machine.current_state(), 1);
+        assert_eq!(machine.read_state(|handled| handled.clone()), [5, 6]);
+    }
+
+    #[test]
+    fn test_urgent_stop() {
+        let machine = StateMachineBuilder::create(1u32, 0u32)
+            .on_mut(5, |fired| *fired += 1)
+            .urgent_stop()
+            .build_active_manual(|_, _| None);
+        let model = Arc::clone(&machine.handle.machine);
+
+        machine.start();
+        machine.step().unwrap();
+        machine.fire_priority(5, Priority::High).unwrap();
+        machine.stop();
+
+        assert_eq!(*model.read().unwrap().model(), 0);
+    }
+
+    #[test]
+    fn test_stop_after_drain() {
+        let machine = StateMachineBuilder::create(1u32, 0u32)
+            .on_mut(5, |fired| *fired += 1)
+            .urgent_stop()
+            .build_active_manual(|_, _| None);
+        let model = Arc::clone(&machine.handle.machine);
+        let handle = machine.handle();
+
+        machine.start();
+        machine.fire(5).unwrap();
+        machine.fire_priority(5, Priority::Low).unwrap();
+        machine.stop_after_drain();
+
+        assert_eq!(*model.read().unwrap().model(), 2);
+        assert!(matches!(handle.fire(5), Err(FireError::Stopped(5))));
+    }
+
+    #[test]
+    fn test_drop_stops_machine() {
+        let machine = StateMachineBuilder::create(1u32, ())
+            .on(5, || {})
+            .build_active(|_, _| None);
+        let handle = machine.handle();
+
+        machine.start();
+        assert!(matches!(
+            handle.fire_and_wait(5),
+            FireOutcome::Stayed { .. }
+        ));
+
+        drop(machine);
+        assert!(matches!(handle.fire(5), Err(FireError::Stopped(5))));
+        assert!(matches!(
+            handle.fire_with_timeout(5, Duration::from_secs(1)),
+            FireOutcome::Stopped
+        ));
+    }
+
+    #[test]
+    fn test_stop_now() {
+        let machine = StateMachineBuilder::create(1u32, 0u32)
+            .on_mut(5, |fired| *fired += 1)
+            .build_active_manual(|_, _| None);
+        let model = Arc::clone(&machine.handle.machine);
+
+        machine.start();
+        machine.fire(5).unwrap();
+        machine.step().unwrap();
+        machine.step().unwrap();
+        machine.fire(6).unwrap();
+        machine.fire_priority(7, Priority::High).unwrap();
+
+        assert_eq!(machine.stop_now(), [7, 6]);
+        assert_eq!(*model.read().unwrap().model(), 1);
+    }
+
+    #[test]
+    fn test_pause_and_resume() {
+        let machine = StateMachineBuilder::create(1u32, 0u32)
+            .on_mut(5, |fired| *fired += 1)
+            .build_active_manual(|state, _| (*state == 1).then_some(2));
+
+        machine.pause();
+        machine.start();
+        machine.fire(5).unwrap();
+        for _ in 0..3 {
+            machine.step().unwrap();
+        }
+
+        assert!(machine.is_paused());
+        assert_eq!(machine.read(|state, fired| (*state, *fired)), (1, 0));
+
+        machine.resume();
+        for _ in 0..3 {
+            machine.step().unwrap();
+        }
+
+        assert!(!machine.is_paused());
+        assert_eq!(machine.read(|state, fired| (*state, *fired)), (2, 1));
+    }
+
+    #[test]
+    fn test_reset_before_later_events() {
+        let machine = StateMachineBuilder::create(1u32, Vec::new())
+            .on_mut(5, |handled| handled.push(5))
+            .goto(2)
+            .on_mut(6, |handled| handled.push(6))
+            .in_state(2)
+            .on_mut(6, |handled| handled.push(60))
+            .build_active_manual(|_, _| None);
+
+        machine.start();
+        machine.fire(5).unwrap();
+        machine.reset();
+        machine.fire(6).unwrap();
+        for _ in 0..4 {
+            machine.step().unwrap();
+        }
+
+        let handled = machine.read(|state, handled| (*state, handled.clone()));
+        assert_eq!(handled, (1, vec![5, 6]));
+    }
+
+    #[test]
+    fn test_reset_waits_while_paused() {
+        let machine = StateMachineBuilder::create(1u32, 0u32)
+            .on_mut(5, |fired| *fired += 1)
+            .goto(2)
+            .build_active_manual(|_, _| None);
+
+        machine.start();
+        machine.pause();
+        machine.fire(5).unwrap();
+        machine.reset();
+        for _ in 0..4 {
+            machine.step().unwrap();
+        }
+        assert_eq!(machine.queue_stats().depth, 2);
+
+        machine.resume();
+        for _ in 0..3 {
+            machine.step().unwrap();
+        }
+        assert_eq!(machine.read(|state, fired| (*state, *fired)), (1, 1));
+    }
+
+    #[test]
+    fn test_reject_while_paused() {
+        let machine = StateMachineBuilder::create(1u32, 0u32)
+            .on_mut(5, |fired| *fired += 1)
+            .while_paused(WhilePaused::Reject)
+            .build_active(|_, _| None);
+        machine.start();
+        machine.pause();
+
+        assert!(matches!(machine.fire(5), Err(FireError::Paused(5))));
+        assert!(matches!(machine.fire_and_wait(5), FireOutcome::Paused));
+
+        machine.resume();
+        assert!(matches!(
+            machine.fire_and_wait(5),
+            FireOutcome::Stayed { state: 1 }
+        ));
+
+        machine.stop();
+    }
+
+    #[test]
+    fn test_active_reset() {
+        let machine = StateMachineBuilder::create(1u32, 0u32)
+            .on_enter_mut(|entered| *entered += 1)
+            .on(5, || {})
+            .goto(2)
+            .build_active_manual(|_, _| None);
+
+        let transitions = machine.subscribe();
+
+        machine.start();
+        machine.fire(5).unwrap();
+        machine.reset_with(|entered| *entered = 10);
+        for _ in 0..3 {
+            machine.step().unwrap();
+        }
+
+        assert!(machine.is_running());
+        assert_eq!(machine.read(|state, entered| (*state, *entered)), (1, 11));
+
+        let transitions: Vec<_> = transitions.try_iter().collect();
+        assert_eq!(
+            transitions,
+            [
+                TransitionEvent {
+                    from: 1,
+                    to: 2,
+                    event: Some(5)
+                },
+                TransitionEvent {
+                    from: 2,
+                    to: 1,
+                    event: None
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_fire_and_wait() {
+        const IDLE: u32 = 1;
+        const RUNNING: u32 = 2;
+        const GO: u32 = 10;
+        const PING: u32 = 11;
+        const BREAK: u32 = 12;
+
+        let machine = StateMachineBuilder::create(IDLE, ())
+            .on(GO, || {})
+            .goto(RUNNING)
+            .in_state(RUNNING)
+            .on(PING, || {})
+            .try_on_mut(BREAK, |_| Err("broken"))
+            .build_active(|_, _| None);
+        machine.start();
+
+        assert!(matches!(
+            machine.fire_and_wait(GO),
+            FireOutcome::Transitioned {
+                from: IDLE,
+                to: RUNNING
+            }
+        ));
+        assert!(matches!(
+            machine.fire_and_wait(PING),
+            FireOutcome::Stayed { state: RUNNING }
+        ));
+        assert!(matches!(
+            machine.fire_and_wait(GO),
+            FireOutcome::Unhandled { state: RUNNING }
+        ));
+        match machine.fire_and_wait(BREAK) {
+            FireOutcome::Failed { error, state } => {
+                assert_eq!(error.to_string(), "event handler #0 failed: broken");
+                assert_eq!(state, RUNNING);
+            }
+            outcome => panic!("unexpected outcome: {outcome:?}"),
+        }
+
+        machine.stop();
+    }
+
+    #[test]
+    fn test_machine_handle() {
+        let machine = StateMachineBuilder::create(1u32, 0u32)
+            .on_mut(5, |fired| *fired += 1)
+            .build_active(|_, _| None);
+        machine.start();
+
+        let senders: Vec<_> = (0..4)
+            .map(|_| {
+                let handle = machine.handle();
+                thread::spawn(move || {
+                    for _ in 0..10 {
+                        handle.fire(5).unwrap();
+                    }
+                    handle.fire_and_wait(5);
+                })
+            })
+            .collect();
+        for sender in senders {
+            sender.join().unwrap();
+        }
+
+        let handle = machine.handle();
+        assert_eq!(handle.read_state(|fired| *fired), 44);
+
+        machine.stop();
+        assert!(matches!(handle.fire(5), Err(FireError::Stopped(5))));
+        assert_eq!(handle.current_state(), 1);
+    }
+
+    #[test]
+    fn test_fire_with_timeout() {
+        let machine = StateMachineBuilder::create(1u32, ())
+            .on(5, || thread::sleep(Duration::from_millis(200)))
+            .build_active(|_, _| None);
+        machine.start();
+
+        assert!(matches!(
+            machine.fire_with_timeout(5, Duration::from_millis(10)),
+            FireOutcome::TimedOut
+        ));
+
+        machine.stop();
+    }
+
+    #[test]
+    fn test_subscribe() {
+        const IDLE: u32 = 1;
+        const RUNNING: u32 = 2;
+        const DONE: u32 = 3;
+        const GO: u32 = 10;
+
+        let machine = StateMachineBuilder::create(IDLE, ())
+            .on(GO, || {})
+            .goto(RUNNING)
+            .build_active(|state, _| (*state == RUNNING).then_some(DONE));
+
+        let first = machine.subscribe();
+        let second = machine.subscribe();
+
+        machine.start();
+        machine.fire(GO).unwrap();
+        assert!(machine.wait_for_state(&DONE, Duration::from_secs(1)));
+
+        let expected = [
+            TransitionEvent {
+                from: IDLE,
+                to: RUNNING,
+                event: Some(GO),
+            },
+            TransitionEvent {
+                from: RUNNING,
+                to: DONE,
+                event: None,
+            },
+        ];
+        assert_eq!(first.try_iter().collect::<Vec<_>>(), expected);
+        assert_eq!(second.try_iter().collect::<Vec<_>>(), expected);
+
+        assert!(!machine.wait_for_state(&IDLE, Duration::from_millis(10)));
+
+        machine.stop();
+    }
+
+    #[test]
+    fn test_read_current_state() {
+        const IDLE: u32 = 1;
+        const RUNNING: u32 = 2;
+        const GO: u32 = 10;
+        const CRASH: u32 = 11;
+
+        let machine = StateMachineBuilder::create(IDLE, 5u32)
+            .on(GO, || {})
+            .goto(RUNNING)
+            .in_state(RUNNING)
+            .on(CRASH, || panic!("crashed"))
+            .build_active(|_, _| None);
+
+        assert!(!machine.is_running());
+        machine.start();
+        machine.fire_and_wait(GO);
+
+        assert!(machine.is_running());
+        assert_eq!(machine.current_state(), RUNNING);
+        assert_eq!(machine.read(|state, model| (*state, *model)), (RUNNING, 5));
+
+        // The panic ends the loop, so waiting gives up
+        assert!(matches!(machine.fire_and_wait(CRASH), FireOutcome::Stopped));
+        assert!(!machine.is_running());
+    }
+
+    #[test]
+    fn test_active_permitted_events() {
+        const IDLE: u32 = 1;
+        const RUNNING: u32 = 2;
+        const GO: u32 = 10;
+        const HALT: u32 = 11;
+
+        let machine = StateMachineBuilder::create(IDLE, ())
+            .on(GO, || {})
+            .goto(RUNNING)
+            .in_state(RUNNING)
+            .on(HALT, || {})
+            .goto(IDLE)
+            .build_active(|_, _| None);
+
+        machine.start();
+        assert_eq!(machine.permitted_events(), [GO]);
+
+        machine.fire_and_wait(GO);
+
+        assert!(machine.can_fire(&HALT));
+        assert!(!machine.can_fire(&GO));
+
+        machine.stop();
+    }
 }
@@ -21,58 +21,129 @@
 // SOFTWARE.
 
 use crate::active::ActiveStateMachine;
-use crate::machine::passive::PassiveStateMachine;
+use crate::error::{BoxError, HandlerError};
+use crate::machine::passive::{GuardFn, HandlerRef, PassiveStateMachine, TransitionCtx};
+use crate::queue::{Overflow, QueueConfig, WhilePaused};
 use std::hash::Hash;
+use std::marker::PhantomData;
+use std::mem;
 
-pub struct StateMachineBuilder<TState: Eq + Hash + Copy, TModel = (), TEvent: Eq + Hash + Copy = ()>
-{
-    working_on_state: TState,
-    working_on_event: Option<TEvent>,
+/// Marks a builder that adds to the state given by `create` or `in_state`
+pub struct InState;
+
+/// Marks a builder that adds to every state, after `in_any_state`. Enter and leave handlers
+/// can't be added to every state, so they aren't available.
+pub struct AnyState;
+
+/// What a call to `goto` applies to
+enum GotoScope<TEvent> {
+    None,
+    Event(TEvent),
+    Error,
+}
+
+pub struct StateMachineBuilder<
+    TState: Eq + Hash + Clone,
+    TModel = (),
+    TEvent: Eq + Hash + Clone = (),
+    TScope = InState,
+> {
+    // None when working on every state with `in_any_state`
+    working_on_state: Option<TState>,
+    scope: PhantomData<TScope>,
+    goto_scope: GotoScope<TEvent>,
+    // The handler or guard that `label` applies to
+    last_handler: Option<HandlerRef<TState, TEvent>>,
+    queue: QueueConfig,
     current_state_machine: PassiveStateMachine<TState, TModel, TEvent>,
 }
 
-impl<TState, TModel, TEvent> StateMachineBuilder<TState, TModel, TEvent>
+impl<TState, TModel, TEvent> StateMachineBuilder<TState, TModel, TEvent, InState>
 where
-    TState: Eq + Hash + Copy + Sync + Send + 'static,
+    TState: Eq + Hash + Clone + Sync + Send + 'static,
     TModel: Sync + Send + 'static,
-    TEvent: Eq + Hash + Copy + Sync + Send + 'static,
+    TEvent: Eq + Hash + Clone + Sync + Send + 'static,
 {
     /// Create a state machine builder that starts in the given state
     pub fn create(initial_state: TState, initial_model: TModel) -> Self {
         Self {
-            working_on_state: initial_state,
-            working_on_event: None,
+            working_on_state: Some(initial_state.clone()),
+            scope: PhantomData,
+            goto_scope: GotoScope::None,
+            last_handler: None,
+            queue: QueueConfig::default(),
             current_state_machine: PassiveStateMachine::new(initial_state, initial_model),
         }
     }
 
-    /// Change the builder context to operate on the given state
-    pub fn in_state(self, state: TState) -> Self {
-        Self {
-            working_on_state: state,
-            working_on_event: None,
-            ..self
-        }
-    }
-
     pub fn on_enter(self, func: impl Fn() + 'static + Sync + Send) -> Self {
-        let mut builder = self;
-        let machine = &mut builder.current_state_machine;
-        machine.add_enter_handler(builder.working_on_state, move |_: &mut TModel| func());
-        builder
+        let wrapper = move |_: &mut TModel| func();
+        self.on_enter_mut(wrapper)
     }
 
     /// Run the given function when the state specified by `in_state` is entered
     pub fn on_enter_mut(self, func: impl Fn(&mut TModel) + 'static + Sync + Send) -> Self {
+        let wrapper = move |_: &TransitionCtx<TState, TEvent>, model: &mut TModel| func(model);
+        self.on_enter_with(wrapper)
+    }
+
+    /// Run the given function when the state specified by `in_state` is entered, with access to
+    /// the transition that caused it
+    pub fn on_enter_with(
+        self,
+        func: impl Fn(&TransitionCtx<TState, TEvent>, &mut TModel) + 'static + Sync + Send,
+    ) -> Self {
+        let wrapper = move |ctx: &TransitionCtx<TState, TEvent>, model: &mut TModel| {
+            func(ctx, model);
+            Ok::<(), BoxError>(())
+        };
+        self.try_on_enter_with(wrapper)
+    }
+
+    /// Like `on_enter_mut`, but an error aborts the transition
+    pub fn try_on_enter_mut<E: Into<BoxError>>(
+        self,
+        func: impl Fn(&mut TModel) -> Result<(), E> + 'static + Sync + Send,
+    ) -> Self {
+        let wrapper = move |_: &TransitionCtx<TState, TEvent>, model: &mut TModel| func(model);
+        self.try_on_enter_with(wrapper)
+    }
+
+    /// Like `on_enter_with`, but an error aborts the transition
+    pub fn try_on_enter_with<E: Into<BoxError>>(
+        self,
+        func: impl Fn(&TransitionCtx<TState, TEvent>, &mut TModel) -> Result<(), E>
+        + 'static
+        + Sync
+        + Send,
+    ) -> Self {
         let mut builder = self;
 
         let machine = &mut builder.current_state_machine;
 
-        machine.add_enter_handler(builder.working_on_state, func);
+        let state = builder
+            .working_on_state
+            .clone()
+            .expect("only builders for a single state can add enter handlers");
+
+        machine.add_enter_handler(state.clone(), move |ctx, model| {
+            func(ctx, model).map_err(Into::into)
+        });
+
+        builder.last_handler = Some(HandlerRef::Enter(state));
 
         builder
     }
 
+    /// Like `on_enter_mut`, but the handler is given a name that introspection and diagrams show
+    pub fn on_enter_named(
+        self,
+        name: impl Into<String>,
+        func: impl Fn(&mut TModel) + 'static + Sync + Send,
+    ) -> Self {
+        self.on_enter_mut(func).label(name)
+    }
+
     pub fn on_leave(self, func: impl Fn() + 'static + Sync + Send) -> Self {
         let wrapper = move |_: &mut TModel| func();
         self.on_leave_mut(wrapper)
@@ -80,14 +151,99 @@ where
 
     /// Run the given function when the state specified by `in_state` is left
     pub fn on_leave_mut(self, func: impl Fn(&mut TModel) + 'static + Sync + Send) -> Self {
+        let wrapper = move |_: &TransitionCtx<TState, TEvent>, model: &mut TModel| func(model);
+        self.on_leave_with(wrapper)
+    }
+
+    /// Run the given function when the state specified by `in_state` is left, with access to the
+    /// transition that caused it
+    pub fn on_leave_with(
+        self,
+        func: impl Fn(&TransitionCtx<TState, TEvent>, &mut TModel) + 'static + Sync + Send,
+    ) -> Self {
+        let wrapper = move |ctx: &TransitionCtx<TState, TEvent>, model: &mut TModel| {
+            func(ctx, model);
+            Ok::<(), BoxError>(())
+        };
+        self.try_on_leave_with(wrapper)
+    }
+
+    /// Like `on_leave_mut`, but an error aborts the transition
+    pub fn try_on_leave_mut<E: Into<BoxError>>(
+        self,
+        func: impl Fn(&mut TModel) -> Result<(), E> + 'static + Sync + Send,
+    ) -> Self {
+        let wrapper = move |_: &TransitionCtx<TState, TEvent>, model: &mut TModel| func(model);
+        self.try_on_leave_with(wrapper)
+    }
+
+    /// Like `on_leave_with`, but an error aborts the transition
+    pub fn try_on_leave_with<E: Into<BoxError>>(
+        self,
+        func: impl Fn(&TransitionCtx<TState, TEvent>, &mut TModel) -> Result<(), E>
+        + 'static
+        + Sync
+        + Send,
+    ) -> Self {
         let mut builder = self;
 
         let machine = &mut builder.current_state_machine;
 
-        machine.add_leave_handler(builder.working_on_state, func);
+        let state = builder
+            .working_on_state
+            .clone()
+            .expect("only builders for a single state can add leave handlers");
+
+        machine.add_leave_handler(state.clone(), move |ctx, model| {
+            func(ctx, model).map_err(Into::into)
+        });
+
+        builder.last_handler = Some(HandlerRef::Leave(state));
+
         builder
     }
 
+    /// Like `on_leave_mut`, but the handler is given a name that introspection and diagrams show
+    pub fn on_leave_named(
+        self,
+        name: impl Into<String>,
+        func: impl Fn(&mut TModel) + 'static + Sync + Send,
+    ) -> Self {
+        self.on_leave_mut(func).label(name)
+    }
+}
+
+impl<TState, TModel, TEvent, TScope> StateMachineBuilder<TState, TModel, TEvent, TScope>
+where
+    TState: Eq + Hash + Clone + Sync + Send + 'static,
+    TModel: Sync + Send + 'static,
+    TEvent: Eq + Hash + Clone + Sync + Send + 'static,
+{
+    /// Change the builder context to operate on the given state
+    pub fn in_state(self, state: TState) -> StateMachineBuilder<TState, TModel, TEvent, InState> {
+        self.rescope(Some(state))
+    }
+
+    /// Change the builder context to operate on every state. Events handled with `on` apply to
+    /// any state that doesn't define that event itself.
+    pub fn in_any_state(self) -> StateMachineBuilder<TState, TModel, TEvent, AnyState> {
+        self.rescope(None)
+    }
+
+    fn rescope<TNewScope>(
+        self,
+        state: Option<TState>,
+    ) -> StateMachineBuilder<TState, TModel, TEvent, TNewScope> {
+        StateMachineBuilder {
+            working_on_state: state,
+            scope: PhantomData,
+            goto_scope: GotoScope::None,
+            last_handler: None,
+            queue: self.queue,
+            current_state_machine: self.current_state_machine,
+        }
+    }
+
     pub fn on(self, event: TEvent, func: impl Fn() + 'static + Sync + Send) -> Self {
         let wrapper = move |_: &mut TModel| func();
         self.on_mut(event, wrapper)
@@ -95,36 +251,209 @@ where
 
     /// Run the given function when the event is fired in the state specified by `in_state`
     pub fn on_mut(self, event: TEvent, func: impl Fn(&mut TModel) + 'static + Sync + Send) -> Self {
+        let wrapper = move |model: &mut TModel| {
+            func(model);
+            Ok::<(), BoxError>(())
+        };
+        self.try_on_mut(event, wrapper)
+    }
+
+    /// Like `on_mut`, but an error aborts the transition and skips the remaining handlers
+    pub fn try_on_mut<E: Into<BoxError>>(
+        self,
+        event: TEvent,
+        func: impl Fn(&mut TModel) -> Result<(), E> + 'static + Sync + Send,
+    ) -> Self {
         let mut builder = self;
-        builder.working_on_event = Some(event);
+        builder.goto_scope = GotoScope::Event(event.clone());
 
         let machine = &mut builder.current_state_machine;
 
-        machine.add_event_handler(builder.working_on_state, event, func);
+        machine.add_event_handler(
+            builder.working_on_state.clone(),
+            event.clone(),
+            move |model| func(model).map_err(Into::into),
+        );
+
+        builder.last_handler = Some(HandlerRef::Event(builder.working_on_state.clone(), event));
 
         builder
     }
 
+    /// Like `on_mut`, but the handler is given a name that introspection and diagrams show
+    pub fn on_named(
+        self,
+        event: TEvent,
+        name: impl Into<String>,
+        func: impl Fn(&mut TModel) + 'static + Sync + Send,
+    ) -> Self {
+        self.on_mut(event, func).label(name)
+    }
+
+    /// Run the given function when an event is fired that the state specified by `in_state`
+    /// doesn't handle. Handlers given in `in_any_state` run for states that have none of their own.
+    pub fn on_unhandled(
+        self,
+        func: impl Fn(&TState, &TEvent, &mut TModel) + 'static + Sync + Send,
+    ) -> Self {
+        let mut builder = self;
+
+        let machine = &mut builder.current_state_machine;
+
+        machine.add_unhandled_handler(builder.working_on_state.clone(), func);
+
+        builder.last_handler = Some(HandlerRef::Unhandled(builder.working_on_state.clone()));
+
+        builder
+    }
+
+    /// Bring handler failures in the state specified by `in_state` (or `in_any_state`) into
+    /// scope, so that `goto` moves the machine to an error state when a handler fails
+    pub fn on_error(self) -> Self {
+        Self {
+            goto_scope: GotoScope::Error,
+            last_handler: None,
+            ..self
+        }
+    }
+
     /// Transition from the state specified by `in_state` to the given state when the event
-    /// specified by `on` is fired.
+    /// specified by `on` is fired, or when a handler fails after `on_error`.
     pub fn goto(self, state: TState) -> Self {
+        self.add_goto(state, None)
+    }
+
+    /// Like `goto`, but the transition is only taken if the guard returns true once the event's
+    /// handlers have run. Guards can't be used with `on_error`.
+    pub fn goto_if(
+        self,
+        state: TState,
+        guard: impl Fn(&TModel) -> bool + 'static + Sync + Send,
+    ) -> Self {
+        if let GotoScope::Error = self.goto_scope {
+            panic!("Can't add a guard to an error transition")
+        }
+
         let mut builder = self;
 
-        match builder.working_on_event {
-            Some(e) => {
+        let event = match &builder.goto_scope {
+            GotoScope::Event(e) => Some(e.clone()),
+            _ => None,
+        };
+
+        builder = builder.add_goto(state, Some(Box::new(guard)));
+
+        if let Some(event) = event {
+            builder.last_handler = Some(HandlerRef::Guard(builder.working_on_state.clone(), event));
+        }
+
+        builder
+    }
+
+    fn add_goto(self, state: TState, guard: Option<Box<GuardFn<TModel>>>) -> Self {
+        let mut builder = self;
+
+        match mem::replace(&mut builder.goto_scope, GotoScope::None) {
+            GotoScope::Event(e) => {
+                builder.current_state_machine.add_transition(
+                    e,
+                    builder.working_on_state.clone(),
+                    state,
+                    guard,
+                );
+            }
+            GotoScope::Error => {
                 builder
                     .current_state_machine
-                    .add_transition(e, builder.working_on_state, state);
-                builder.working_on_event = None;
+                    .add_error_transition(builder.working_on_state.clone(), state);
             }
-            None => {
-                panic!("Can't add a transition before an event is in scope with on()")
+            GotoScope::None => {
+                panic!("Can't add a transition before an event is in scope with on() or on_error()")
             }
         }
 
+        builder.last_handler = None;
+
         builder
     }
 
+    /// Name the handler or guard that was just added, for introspection and diagrams such as
+    /// `to_plantuml`. Any kind of handler can be named this way, including fallible, `_with` and
+    /// unhandled event handlers; the `_named` methods are shorthand for the plain ones.
+    pub fn label(self, label: impl Into<String>) -> Self {
+        let mut builder = self;
+
+        match &builder.last_handler {
+            Some(handler) => builder.current_state_machine.label(handler, label.into()),
+            None => panic!("Can't add a label before a handler or guard is added"),
+        }
+
+        builder
+    }
+
+    /// Turn panics in handlers into failures, so they abort the transition like an error would and
+    /// an active machine keeps running
+    pub fn catch_panics(self) -> Self {
+        let mut builder = self;
+        builder.current_state_machine.set_catch_panics(true);
+        builder.last_handler = None;
+        builder
+    }
+
+    /// Run the given function whenever a handler in any state fails
+    pub fn on_failure(
+        self,
+        func: impl Fn(&HandlerError<TState, TEvent>) + 'static + Sync + Send,
+    ) -> Self {
+        let mut builder = self;
+        builder.current_state_machine.add_failure_handler(func);
+        builder.last_handler = None;
+        builder
+    }
+
+    /// Limit how many events an active machine queues. The overflow policy decides what firing an
+    /// event into a full queue does. Machines built with `build_active_manual` treat
+    /// `Overflow::Block` as `Overflow::Error`, since nothing else would make room.
+    pub fn bounded_queue(self, capacity: usize, overflow: Overflow) -> Self {
+        if capacity == 0 {
+            panic!("The queue must hold at least one event");
+        }
+
+        Self {
+            queue: QueueConfig {
+                capacity: Some(capacity),
+                overflow,
+                ..self.queue
+            },
+            last_handler: None,
+            ..self
+        }
+    }
+
+    /// Have an active machine's `stop` jump ahead of any queued events, which are then discarded
+    pub fn urgent_stop(self) -> Self {
+        Self {
+            queue: QueueConfig {
+                urgent_stop: true,
+                ..self.queue
+            },
+            last_handler: None,
+            ..self
+        }
+    }
+
+    /// Choose what firing an event into a paused active machine does
+    pub fn while_paused(self, while_paused: WhilePaused) -> Self {
+        Self {
+            queue: QueueConfig {
+                while_paused,
+                ..self.queue
+            },
+            last_handler: None,
+            ..self
+        }
+    }
+
     /// Create a passive state machine, finalizing the builder
     pub fn build_passive(self) -> PassiveStateMachine<TState, TModel, TEvent> {
         self.current_state_machine
@@ -135,6 +464,15 @@ where
         self,
         tick: impl Fn(&TState, &TModel) -> Option<TState> + Send + Sync + 'static,
     ) -> ActiveStateMachine<TState, TModel, TEvent> {
-        ActiveStateMachine::create(tick, self.current_state_machine)
+        ActiveStateMachine::create(tick, self.current_state_machine, self.queue)
+    }
+
+    /// Create an active state machine that only runs when `step` is called, finalizing the
+    /// builder. Useful for tests that shouldn't depend on timing.
+    pub fn build_active_manual(
+        self,
+        tick: impl Fn(&TState, &TModel) -> Option<TState> + Send + Sync + 'static,
+    ) -> ActiveStateMachine<TState, TModel, TEvent> {
+        ActiveStateMachine::create_manual(tick, self.current_state_machine, self.queue)
     }
 }
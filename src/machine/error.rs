@@ -0,0 +1,177 @@
+// MIT License
+//
+// Copyright (c) 2024 Wes Kelly
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use std::any::Any;
+use std::error::Error;
+use std::fmt::{Debug, Display, Formatter};
+
+/// The error type returned by fallible handlers
+pub type BoxError = Box<dyn Error + Send + Sync + 'static>;
+
+/// The kind of handler that failed
+#[derive(Debug, Eq, PartialEq, Copy, Clone, Hash)]
+pub enum HandlerKind {
+    Event,
+    Enter,
+    Leave,
+    /// The guard given to `goto_if`
+    Guard,
+    /// A handler given to `on_unhandled`
+    Unhandled,
+    /// The function given to `build_active`
+    Tick,
+}
+
+/// Returned by an active machine's `fire` when the event can't be queued. The event is handed
+/// back.
+pub enum FireError<TEvent> {
+    /// The queue is full and its overflow policy doesn't allow waiting or discarding older events
+    Full(TEvent),
+    /// The machine has stopped
+    Stopped(TEvent),
+    /// The machine is paused and was built to reject events until it resumes
+    Paused(TEvent),
+}
+
+impl<TEvent> FireError<TEvent> {
+    /// The event that couldn't be queued
+    pub fn into_event(self) -> TEvent {
+        match self {
+            FireError::Full(event) | FireError::Stopped(event) | FireError::Paused(event) => event,
+        }
+    }
+}
+
+impl<TEvent> Display for FireError<TEvent> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FireError::Full(_) => write!(f, "the event queue is full"),
+            FireError::Stopped(_) => write!(f, "the machine has stopped"),
+            FireError::Paused(_) => write!(f, "the machine is paused"),
+        }
+    }
+}
+
+// Written by hand so that events don't need to implement `Debug`
+impl<TEvent> Debug for FireError<TEvent> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FireError::Full(_) => write!(f, "Full(..)"),
+            FireError::Stopped(_) => write!(f, "Stopped(..)"),
+            FireError::Paused(_) => write!(f, "Paused(..)"),
+        }
+    }
+}
+
+impl<TEvent> Error for FireError<TEvent> {}
+
+/// Returned by an active machine's `step` when the machine runs on its own thread. Only machines
+/// built with `build_active_manual` can be stepped.
+#[derive(Debug, Eq, PartialEq, Copy, Clone, Hash)]
+pub struct StepError;
+
+impl Display for StepError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "only machines built with build_active_manual can be stepped"
+        )
+    }
+}
+
+impl Error for StepError {}
+
+/// Returned by `fire` when a handler fails. The transition is aborted and the remaining handlers
+/// are skipped, but changes the earlier handlers made to the model are kept.
+pub struct HandlerError<TState, TEvent> {
+    /// The state the machine was in when the handler failed
+    pub state: TState,
+    /// The event being handled, or `None` if the transition wasn't caused by `fire`
+    pub event: Option<TEvent>,
+    /// The kind of handler that failed
+    pub kind: HandlerKind,
+    /// The position of the failed handler, in order of definition
+    pub index: usize,
+    /// The error returned by the handler
+    pub cause: BoxError,
+}
+
+impl<TState, TEvent> Display for HandlerError<TState, TEvent> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let kind = match self.kind {
+            HandlerKind::Event => "event",
+            HandlerKind::Enter => "enter",
+            HandlerKind::Leave => "leave",
+            HandlerKind::Guard => "guard",
+            HandlerKind::Unhandled => "unhandled event",
+            HandlerKind::Tick => "tick",
+        };
+
+        write!(f, "{kind} handler #{} failed: {}", self.index, self.cause)
+    }
+}
+
+// States and events aren't required to implement Debug, so they're left out
+impl<TState, TEvent> Debug for HandlerError<TState, TEvent> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("HandlerError")
+            .field("kind", &self.kind)
+            .field("index", &self.index)
+            .field("cause", &self.cause)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<TState, TEvent> Error for HandlerError<TState, TEvent> {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(self.cause.as_ref())
+    }
+}
+
+/// The cause of a `HandlerError` when a handler panicked in a machine built with `catch_panics`
+#[derive(Debug)]
+pub struct HandlerPanic {
+    /// The panic message, if it was a string
+    pub message: Option<String>,
+}
+
+impl HandlerPanic {
+    pub(crate) fn from_payload(payload: Box<dyn Any + Send>) -> Self {
+        let message = match payload.downcast::<String>() {
+            Ok(message) => Some(*message),
+            Err(payload) => payload.downcast_ref::<&str>().map(|s| s.to_string()),
+        };
+
+        Self { message }
+    }
+}
+
+impl Display for HandlerPanic {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match &self.message {
+            Some(message) => write!(f, "handler panicked: {message}"),
+            None => write!(f, "handler panicked"),
+        }
+    }
+}
+
+impl Error for HandlerPanic {}
@@ -0,0 +1,587 @@
+// MIT License
+//
+// Copyright (c) 2024 Wes Kelly
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Load a machine's structure from TOML or JSON, binding handlers and guards by name.
+//!
+//! ```toml
+//! initial = "Locked"
+//!
+//! [[states]]
+//! name = "Locked"
+//! on_enter = ["print_locked"]
+//!
+//! [[states]]
+//! name = "Unlocked"
+//! timeout = { after_ms = 5000, goto = "Locked" }
+//!
+//! [[transitions]]
+//! from = "Locked"
+//! event = "Coin"
+//! handlers = ["add_coin"]
+//! guard = "paid_enough"
+//! to = "Unlocked"
+//!
+//! # Leaving out `from` applies the transition to any state
+//! [[transitions]]
+//! event = "Reset"
+//! to = "Locked"
+//! ```
+//!
+//! Any other format supported by serde, such as YAML, can be used by deserializing a
+//! `MachineDefinition` with its serde crate. Only TOML and JSON are built in.
+
+use crate::active::ActiveStateMachine;
+use crate::passive::{HandlerRef, PassiveStateMachine};
+use crate::queue::QueueConfig;
+pub use crate::registry::HandlerRegistry;
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::hash::Hash;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// The structure of a machine, with handlers and guards referred to by name
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MachineDefinition {
+    pub initial: String,
+    #[serde(default)]
+    pub states: Vec<StateDefinition>,
+    #[serde(default)]
+    pub transitions: Vec<TransitionDefinition>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct StateDefinition {
+    pub name: String,
+    #[serde(default)]
+    pub on_enter: Vec<String>,
+    #[serde(default)]
+    pub on_leave: Vec<String>,
+    /// Only supported by active machines
+    pub timeout: Option<TimeoutDefinition>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TimeoutDefinition {
+    pub after_ms: u64,
+    pub goto: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TransitionDefinition {
+    /// The state the event is handled in, or any state if left out
+    pub from: Option<String>,
+    pub event: String,
+    #[serde(default)]
+    pub handlers: Vec<String>,
+    pub guard: Option<String>,
+    pub to: Option<String>,
+}
+
+#[derive(Debug)]
+pub enum LoadError {
+    /// The text isn't a valid machine definition
+    Parse(String),
+    UnknownState(String),
+    UnknownEvent(String),
+    UnknownHandler(String),
+    UnknownGuard(String),
+    /// A transition has a guard but nowhere to go
+    GuardWithoutTarget {
+        event: String,
+    },
+    /// A transition has neither handlers nor somewhere to go
+    EmptyTransition {
+        event: String,
+    },
+    /// The same event is given more than one transition from the same state, or from any state
+    DuplicateTransition {
+        from: Option<String>,
+        event: String,
+    },
+    /// The definition has timeouts, which passive machines can't act on
+    TimeoutsNeedActiveMachine,
+}
+
+impl MachineDefinition {
+    pub fn from_toml(text: &str) -> Result<Self, LoadError> {
+        toml::from_str(text).map_err(|e| LoadError::Parse(e.to_string()))
+    }
+
+    pub fn from_json(text: &str) -> Result<Self, LoadError> {
+        serde_json::from_str(text).map_err(|e| LoadError::Parse(e.to_string()))
+    }
+
+    /// Create a passive state machine, binding handlers and guards from the registry
+    pub fn build_passive<TState, TModel, TEvent>(
+        &self,
+        registry: &HandlerRegistry<TModel>,
+        model: TModel,
+    ) -> Result<PassiveStateMachine<TState, TModel, TEvent>, LoadError>
+    where
+        TState: Eq + Hash + Clone + FromStr + Sync + Send + 'static,
+        TModel: Sync + Send + 'static,
+        TEvent: Eq + Hash + Clone + FromStr + Sync + Send + 'static,
+    {
+        if self.states.iter().any(|state| state.timeout.is_some()) {
+            return Err(LoadError::TimeoutsNeedActiveMachine);
+        }
+
+        self.machine(registry, model)
+    }
+
+    /// Create an active state machine, binding handlers and guards from the registry. Timeouts
+    /// are measured from when the machine last entered its state.
+    pub fn build_active<TState, TModel, TEvent>(
+        &self,
+        registry: &HandlerRegistry<TModel>,
+        model: TModel,
+    ) -> Result<ActiveStateMachine<TState, TModel, TEvent>, LoadError>
+    where
+        TState: Eq + Hash + Clone + FromStr + Sync + Send + 'static,
+        TModel: Sync + Send + 'static,
+        TEvent: Eq + Hash + Clone + FromStr + Sync + Send + 'static,
+    {
+        let mut machine = self.machine(registry, model)?;
+        let tick = self.timeout_tick(&mut machine)?;
+
+        Ok(ActiveStateMachine::create(
+            tick,
+            machine,
+            QueueConfig::default(),
+        ))
+    }
+
+    /// A tick that takes the machine through its timeouts
+    fn timeout_tick<TState, TModel, TEvent>(
+        &self,
+        machine: &mut PassiveStateMachine<TState, TModel, TEvent>,
+    ) -> Result<impl Fn(&TState, &TModel) -> Option<TState> + 'static + Send + Sync, LoadError>
+    where
+        TState: Eq + Hash + Clone + FromStr + Sync + Send + 'static,
+        TModel: Sync + Send + 'static,
+        TEvent: Eq + Hash + Clone + FromStr + Sync + Send + 'static,
+    {
+        let mut timeouts: HashMap<TState, (Duration, TState)> = HashMap::new();
+        for state in self.states.iter() {
+            if let Some(timeout) = &state.timeout {
+                let after = Duration::from_millis(timeout.after_ms);
+                timeouts.insert(
+                    parse_state(&state.name)?,
+                    (after, parse_state(&timeout.goto)?),
+                );
+            }
+        }
+
+        // Starting and every transition restart the clock, even a transition that leaves and
+        // comes back between ticks
+        let entered: Arc<Mutex<Option<Instant>>> = Arc::default();
+        let on_start = Arc::clone(&entered);
+        machine.add_start_listener(move |_| *on_start.lock().unwrap() = Some(Instant::now()));
+        let on_transition = Arc::clone(&entered);
+        machine.add_transition_listener(move |_, _, _| {
+            *on_transition.lock().unwrap() = Some(Instant::now());
+        });
+
+        let tick = move |state: &TState, _: &TModel| {
+            let since = (*entered.lock().unwrap())?;
+
+            let (after, to) = timeouts.get(state)?;
+            (since.elapsed() >= *after).then(|| to.clone())
+        };
+
+        Ok(tick)
+    }
+
+    fn machine<TState, TModel, TEvent>(
+        &self,
+        registry: &HandlerRegistry<TModel>,
+        model: TModel,
+    ) -> Result<PassiveStateMachine<TState, TModel, TEvent>, LoadError>
+    where
+        TState: Eq + Hash + Clone + FromStr,
+        TModel: 'static,
+        TEvent: Eq + Hash + Clone + FromStr,
+    {
+        let mut machine = PassiveStateMachine::new(parse_state(&self.initial)?, model);
+
+        for state in self.states.iter() {
+            let name: TState = parse_state(&state.name)?;
+
+            for handler_name in state.on_enter.iter() {
+                let handler = registry
+                    .handler(handler_name)
+                    .ok_or_else(|| LoadError::UnknownHandler(handler_name.clone()))?;
+                machine.add_enter_handler(name.clone(), move |_, model| handler(model));
+                machine.label(&HandlerRef::Enter(name.clone()), handler_name.clone());
+            }
+
+            for handler_name in state.on_leave.iter() {
+                let handler = registry
+                    .handler(handler_name)
+                    .ok_or_else(|| LoadError::UnknownHandler(handler_name.clone()))?;
+                machine.add_leave_handler(name.clone(), move |_, model| handler(model));
+                machine.label(&HandlerRef::Leave(name.clone()), handler_name.clone());
+            }
+        }
+
+        let mut defined = HashSet::new();
+        for transition in self.transitions.iter() {
+            let from = match &transition.from {
+                Some(from) => Some(parse_state(from)?),
+                None => None,
+            };
+            let event = TEvent::from_str(&transition.event)
+                .map_err(|_| LoadError::UnknownEvent(transition.event.clone()))?;
+
+            if !defined.insert((from.clone(), event.clone())) {
+                return Err(LoadError::DuplicateTransition {
+                    from: transition.from.clone(),
+                    event: transition.event.clone(),
+                });
+            }
+
+            for handler_name in transition.handlers.iter() {
+                let handler = registry
+                    .handler(handler_name)
+                    .ok_or_else(|| LoadError::UnknownHandler(handler_name.clone()))?;
+                machine.add_event_handler(from.clone(), event.clone(), move |model| handler(model));
+                machine.label(
+                    &HandlerRef::Event(from.clone(), event.clone()),
+                    handler_name.clone(),
+                );
+            }
+
+            match (&transition.to, &transition.guard) {
+                (Some(to), guard_name) => {
+                    let guard = match guard_name {
+                        Some(guard_name) => {
+                            let guard = registry
+                                .guard(guard_name)
+                                .ok_or_else(|| LoadError::UnknownGuard(guard_name.clone()))?;
+                            Some(Box::new(move |model: &TModel| guard(model)) as _)
+                        }
+                        None => None,
+                    };
+                    machine.add_transition(event.clone(), from.clone(), parse_state(to)?, guard);
+                    if let Some(guard_name) = guard_name {
+                        machine.label(&HandlerRef::Guard(from, event), guard_name.clone());
+                    }
+                }
+                (None, Some(_)) => {
+                    return Err(LoadError::GuardWithoutTarget {
+                        event: transition.event.clone(),
+                    });
+                }
+                (None, None) if transition.handlers.is_empty() => {
+                    return Err(LoadError::EmptyTransition {
+                        event: transition.event.clone(),
+                    });
+                }
+                (None, None) => {}
+            }
+        }
+
+        Ok(machine)
+    }
+}
+
+fn parse_state<TState: FromStr>(name: &str) -> Result<TState, LoadError> {
+    TState::from_str(name).map_err(|_| LoadError::UnknownState(name.to_string()))
+}
+
+impl Display for LoadError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LoadError::Parse(message) => write!(f, "invalid machine definition: {message}"),
+            LoadError::UnknownState(name) => write!(f, "unknown state `{name}`"),
+            LoadError::UnknownEvent(name) => write!(f, "unknown event `{name}`"),
+            LoadError::UnknownHandler(name) => write!(f, "no handler registered as `{name}`"),
+            LoadError::UnknownGuard(name) => write!(f, "no guard registered as `{name}`"),
+            LoadError::GuardWithoutTarget { event } => {
+                write!(f, "transition on `{event}` has a guard but no target state")
+            }
+            LoadError::EmptyTransition { event } => {
+                write!(f, "transition on `{event}` has no handlers or target state")
+            }
+            LoadError::DuplicateTransition {
+                from: Some(from),
+                event,
+            } => write!(f, "`{event}` has more than one transition from `{from}`"),
+            LoadError::DuplicateTransition { from: None, event } => {
+                write!(f, "`{event}` has more than one transition from any state")
+            }
+            LoadError::TimeoutsNeedActiveMachine => {
+                write!(f, "timeouts can only be used by an active machine")
+            }
+        }
+    }
+}
+
+impl Error for LoadError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use Events::*;
+    use States::*;
+    use std::thread;
+
+    #[derive(Eq, PartialEq, Copy, Clone, Hash, Debug)]
+    enum States {
+        Locked,
+        Unlocked,
+    }
+
+    #[derive(Eq, PartialEq, Copy, Clone, Hash, Debug)]
+    enum Events {
+        Coin,
+        Push,
+    }
+
+    impl FromStr for States {
+        type Err = ();
+
+        fn from_str(s: &str) -> Result<Self, Self::Err> {
+            match s {
+                "Locked" => Ok(Locked),
+                "Unlocked" => Ok(Unlocked),
+                _ => Err(()),
+            }
+        }
+    }
+
+    impl FromStr for Events {
+        type Err = ();
+
+        fn from_str(s: &str) -> Result<Self, Self::Err> {
+            match s {
+                "Coin" => Ok(Coin),
+                "Push" => Ok(Push),
+                _ => Err(()),
+            }
+        }
+    }
+
+    #[derive(Default)]
+    struct Turnstile {
+        coins: u32,
+        riders: u32,
+    }
+
+    const TURNSTILE: &str = r#"
+        initial = "Locked"
+
+        [[states]]
+        name = "Unlocked"
+        on_leave = ["add_rider"]
+
+        [[transitions]]
+        from = "Locked"
+        event = "Coin"
+        handlers = ["add_coin"]
+        guard = "paid_enough"
+        to = "Unlocked"
+
+        [[transitions]]
+        event = "Push"
+        to = "Locked"
+    "#;
+
+    fn registry() -> HandlerRegistry<Turnstile> {
+        HandlerRegistry::new()
+            .register("add_coin", |model: &mut Turnstile| model.coins += 1)
+            .register("add_rider", |model: &mut Turnstile| model.riders += 1)
+            .register_guard("paid_enough", |model: &Turnstile| {
+                model.coins.is_multiple_of(2)
+            })
+    }
+
+    #[test]
+    fn test_load_toml() {
+        let definition = MachineDefinition::from_toml(TURNSTILE).unwrap();
+        let mut machine: PassiveStateMachine<States, Turnstile, Events> = definition
+            .build_passive(&registry(), Turnstile::default())
+            .unwrap();
+
+        // Handlers and guards keep their registered names
+        assert_eq!(machine.leave_handler_names(&Unlocked), [Some("add_rider")]);
+        assert_eq!(
+            machine.guard_name(&Locked, &Coin),
+            Some(Some("paid_enough"))
+        );
+
+        machine.start().unwrap();
+
+        // Two coins are needed to get through
+        machine.fire(Coin).unwrap();
+        assert_eq!(*machine.current_state(), Locked);
+
+        machine.fire(Coin).unwrap();
+        assert_eq!(*machine.current_state(), Unlocked);
+
+        // Push is handled in any state
+        machine.fire(Push).unwrap();
+        assert_eq!(*machine.current_state(), Locked);
+        assert_eq!(machine.model().coins, 2);
+        assert_eq!(machine.model().riders, 1);
+    }
+
+    #[test]
+    fn test_load_errors() {
+        let build = |json: &str| {
+            MachineDefinition::from_json(json)?
+                .build_passive::<States, Turnstile, Events>(&registry(), Turnstile::default())
+                .map(|_| ())
+        };
+
+        assert!(matches!(
+            build(r#"{ "initial": "Open" }"#),
+            Err(LoadError::UnknownState(name)) if name == "Open"
+        ));
+        assert!(matches!(
+            build(r#"{ "initial": "Locked", "transitions": [{ "event": "Kick", "to": "Locked" }] }"#),
+            Err(LoadError::UnknownEvent(name)) if name == "Kick"
+        ));
+        assert!(matches!(
+            build(r#"{ "initial": "Locked", "states": [{ "name": "Locked", "on_enter": ["beep"] }] }"#),
+            Err(LoadError::UnknownHandler(name)) if name == "beep"
+        ));
+        assert!(matches!(
+            build(r#"{ "initial": "Locked", "transitions": [{ "event": "Push", "guard": "x", "to": "Locked" }] }"#),
+            Err(LoadError::UnknownGuard(name)) if name == "x"
+        ));
+        assert!(matches!(
+            build(
+                r#"{ "initial": "Locked", "states": [{ "name": "Locked", "timeout": { "after_ms": 5, "goto": "Unlocked" } }] }"#
+            ),
+            Err(LoadError::TimeoutsNeedActiveMachine)
+        ));
+        assert!(matches!(
+            build(r#"{ "initial": "Locked", "transitions": [{ "event": "Push", "guard": "paid_enough" }] }"#),
+            Err(LoadError::GuardWithoutTarget { event }) if event == "Push"
+        ));
+        assert!(matches!(
+            build(r#"{ "initial": "Locked", "transitions": [{ "from": "Locked", "event": "Push" }] }"#),
+            Err(LoadError::EmptyTransition { event }) if event == "Push"
+        ));
+        assert!(matches!(
+            build(
+                r#"{ "initial": "Locked", "transitions": [{ "from": "Locked", "event": "Push", "to": "Locked" }, { "from": "Locked", "event": "Push", "to": "Unlocked" }] }"#
+            ),
+            Err(LoadError::DuplicateTransition { from: Some(from), event }) if from == "Locked" && event == "Push"
+        ));
+        assert!(matches!(
+            build(r#"{ "initial": 5 }"#),
+            Err(LoadError::Parse(_))
+        ));
+    }
+
+    #[test]
+    fn test_timeouts() {
+        let definition = MachineDefinition::from_toml(
+            r#"
+            initial = "Locked"
+
+            [[states]]
+            name = "Unlocked"
+            timeout = { after_ms = 50, goto = "Locked" }
+
+            [[transitions]]
+            from = "Locked"
+            event = "Coin"
+            to = "Unlocked"
+
+            [[transitions]]
+            from = "Unlocked"
+            event = "Push"
+            to = "Locked"
+            "#,
+        )
+        .unwrap();
+
+        let mut machine = definition
+            .machine::<States, Turnstile, Events>(&registry(), Turnstile::default())
+            .unwrap();
+        let tick = definition.timeout_tick(&mut machine).unwrap();
+        let machine = ActiveStateMachine::create_manual(tick, machine, QueueConfig::default());
+
+        machine.start();
+        machine.fire(Coin).unwrap();
+        machine.step().unwrap();
+        machine.step().unwrap();
+        machine.step().unwrap();
+        assert_eq!(machine.current_state(), Unlocked);
+
+        // Leaving and coming back between ticks restarts the timeout
+        thread::sleep(Duration::from_millis(60));
+        machine.fire(Push).unwrap();
+        machine.fire(Coin).unwrap();
+        machine.step().unwrap();
+        machine.step().unwrap();
+        machine.step().unwrap();
+        assert_eq!(machine.current_state(), Unlocked);
+
+        thread::sleep(Duration::from_millis(60));
+        machine.step().unwrap();
+        assert_eq!(machine.current_state(), Locked);
+    }
+
+    #[test]
+    fn test_timeout_starts_with_machine() {
+        let definition = MachineDefinition::from_toml(
+            r#"
+            initial = "Unlocked"
+
+            [[states]]
+            name = "Unlocked"
+            timeout = { after_ms = 50, goto = "Locked" }
+            "#,
+        )
+        .unwrap();
+
+        let mut machine = definition
+            .machine::<States, Turnstile, Events>(&registry(), Turnstile::default())
+            .unwrap();
+        let tick = definition.timeout_tick(&mut machine).unwrap();
+        let machine = ActiveStateMachine::create_manual(tick, machine, QueueConfig::default());
+
+        // A machine that hasn't started isn't ticked, and its clock doesn't run
+        machine.step().unwrap();
+        thread::sleep(Duration::from_millis(60));
+        machine.start();
+        machine.step().unwrap();
+        machine.step().unwrap();
+        assert_eq!(machine.current_state(), Unlocked);
+
+        thread::sleep(Duration::from_millis(60));
+        machine.step().unwrap();
+        assert_eq!(machine.current_state(), Locked);
+    }
+}
@@ -20,70 +20,187 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 // SOFTWARE.
 
-use std::collections::HashMap;
+use crate::error::{BoxError, HandlerError, HandlerKind, HandlerPanic};
+use std::collections::{HashMap, HashSet};
+use std::fmt::{self, Debug, Formatter};
 use std::hash::Hash;
+use std::panic::{self, AssertUnwindSafe};
+
+type HandlerResult = Result<(), BoxError>;
+type EventHandler<TModel> = Labelled<dyn Fn(&mut TModel) -> HandlerResult + 'static + Sync + Send>;
+type StateHandler<TState, TModel, TEvent> = Labelled<
+    dyn Fn(&TransitionCtx<TState, TEvent>, &mut TModel) -> HandlerResult + 'static + Sync + Send,
+>;
+pub(crate) type GuardFn<TModel> = dyn Fn(&TModel) -> bool + 'static + Sync + Send;
+type Guard<TModel> = Labelled<GuardFn<TModel>>;
+type Definition<'a, TState, TModel> = (
+    Option<&'a Vec<EventHandler<TModel>>>,
+    Option<&'a Transition<TState, TModel>>,
+);
+type UnhandledHandler<TState, TModel, TEvent> =
+    Labelled<dyn Fn(&TState, &TEvent, &mut TModel) + 'static + Sync + Send>;
+type FailureHandler<TState, TEvent> =
+    Box<dyn Fn(&HandlerError<TState, TEvent>) + 'static + Sync + Send>;
+type TransitionListener<TState, TEvent> =
+    Box<dyn Fn(&TState, &TState, &Option<TEvent>) + 'static + Sync + Send>;
+type StartListener<TState> = Box<dyn Fn(&TState) + 'static + Sync + Send>;
+
+/// The transition that caused an entry or exit handler to run
+#[derive(Debug, Eq, PartialEq, Copy, Clone, Hash)]
+pub struct TransitionCtx<TState, TEvent> {
+    /// The state being left, or `None` when the initial state is entered by `start`
+    pub from: Option<TState>,
+    /// The state being entered, or `None` when the current state is left by `stop` or `reset`
+    pub to: Option<TState>,
+    /// The event that triggered the transition, or `None` if it wasn't caused by `fire`
+    pub event: Option<TEvent>,
+}
+
+/// What `fire` would do for an event, as reported by `peek`. Handlers are listed by name, and
+/// are `None` if they weren't named.
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct PlannedTransition<'a, TState> {
+    /// The state the event would move the machine to, or `None` if it would stay put
+    pub to: Option<&'a TState>,
+    /// The name of the guard on the move, if it has one. The guard is only checked after the
+    /// event's handlers have run, so a guarded move may not happen.
+    pub guard: Option<Option<&'a str>>,
+    /// The event's handlers, which run first
+    pub event_handlers: Vec<Option<&'a str>>,
+    /// The current state's leave handlers, which run if the machine moves
+    pub leave_handlers: Vec<Option<&'a str>>,
+    /// The target state's enter handlers, which run if the machine moves
+    pub enter_handlers: Vec<Option<&'a str>>,
+    /// The handlers that run instead of everything else when the state doesn't handle the event
+    pub unhandled_handlers: Vec<Option<&'a str>>,
+}
+
+/// What a successfully handled event did
+pub(crate) enum Handled<TState> {
+    Transitioned { from: TState },
+    Stayed,
+    Unhandled,
+}
+
+/// A handler or guard with an optional name for introspection
+pub(crate) struct Labelled<F: ?Sized> {
+    pub(crate) label: Option<String>,
+    pub(crate) func: Box<F>,
+}
+
+impl<F: ?Sized> Labelled<F> {
+    fn new(func: Box<F>) -> Self {
+        Self { label: None, func }
+    }
+}
+
+/// A transition to another state, only taken if the guard (if any) allows it
+pub(crate) struct Transition<TState, TModel> {
+    pub(crate) to: TState,
+    pub(crate) guard: Option<Guard<TModel>>,
+}
+
+/// Where a handler or guard was added, so that it can be labelled afterwards
+pub(crate) enum HandlerRef<TState, TEvent> {
+    Enter(TState),
+    Leave(TState),
+    Event(Option<TState>, TEvent),
+    Guard(Option<TState>, TEvent),
+    Unhandled(Option<TState>),
+}
 
 pub struct PassiveStateMachine<TState, TModel = (), TEvent = ()>
 where
-    TState: Eq + Hash + Copy + Clone,
-    TEvent: Eq + Hash + Copy + Clone,
+    TState: Eq + Hash + Clone,
+    TEvent: Eq + Hash + Clone,
 {
     running: bool,
+    initial_state: TState,
     current_state: TState,
     model: TModel,
 
-    on_event: HashMap<(TState, TEvent), Vec<Box<dyn Fn(&mut TModel) + 'static + Sync + Send>>>,
-    on_enter: HashMap<TState, Vec<Box<dyn Fn(&mut TModel) + 'static + Sync + Send>>>,
-    on_leave: HashMap<TState, Vec<Box<dyn Fn(&mut TModel) + 'static + Sync + Send>>>,
+    on_event: HashMap<(TState, TEvent), Vec<EventHandler<TModel>>>,
+    on_enter: HashMap<TState, Vec<StateHandler<TState, TModel, TEvent>>>,
+    on_leave: HashMap<TState, Vec<StateHandler<TState, TModel, TEvent>>>,
+
+    transitions: HashMap<(TState, TEvent), Transition<TState, TModel>>,
+    error_transitions: HashMap<TState, TState>,
 
-    transitions: HashMap<(TState, TEvent), TState>,
+    // Used for events the current state doesn't define itself
+    any_on_event: HashMap<TEvent, Vec<EventHandler<TModel>>>,
+    any_transitions: HashMap<TEvent, Transition<TState, TModel>>,
+    any_error_transition: Option<TState>,
+
+    on_unhandled: HashMap<TState, Vec<UnhandledHandler<TState, TModel, TEvent>>>,
+    any_on_unhandled: Vec<UnhandledHandler<TState, TModel, TEvent>>,
+
+    catch_panics: bool,
+    on_failure: Vec<FailureHandler<TState, TEvent>>,
+    transition_listeners: Vec<TransitionListener<TState, TEvent>>,
+    start_listeners: Vec<StartListener<TState>>,
+    // States imported from SCXML `<final>` elements
+    final_states: HashSet<TState>,
 }
 
 impl<TState, TModel, TEvent> PassiveStateMachine<TState, TModel, TEvent>
 where
-    TState: Eq + Hash + Copy,
-    TEvent: Eq + Hash + Copy,
+    TState: Eq + Hash + Clone,
+    TEvent: Eq + Hash + Clone,
 {
     pub(crate) fn new(initial_state: TState, model: TModel) -> Self {
         Self {
             running: false,
+            initial_state: initial_state.clone(),
             current_state: initial_state,
             model,
             on_event: HashMap::new(),
             on_enter: HashMap::new(),
             on_leave: HashMap::new(),
             transitions: HashMap::new(),
+            error_transitions: HashMap::new(),
+            any_on_event: HashMap::new(),
+            any_transitions: HashMap::new(),
+            any_error_transition: None,
+            on_unhandled: HashMap::new(),
+            any_on_unhandled: Vec::new(),
+            catch_panics: false,
+            on_failure: Vec::new(),
+            transition_listeners: Vec::new(),
+            start_listeners: Vec::new(),
+            final_states: HashSet::new(),
         }
     }
 
+    /// Add an event handler to the given state, or to every state if `state` is `None`
     pub(crate) fn add_event_handler(
         &mut self,
-        state: TState,
+        state: Option<TState>,
         event: TEvent,
-        func: impl Fn(&mut TModel) + 'static + Sync + Send,
+        func: impl Fn(&mut TModel) -> HandlerResult + 'static + Sync + Send,
     ) {
-        let key = (state, event);
-        match self.on_event.get_mut(&key) {
-            Some(vec) => {
-                vec.push(Box::new(func));
-            }
-            None => {
-                self.on_event.insert(key, vec![Box::new(func)]);
-            }
-        }
+        let handlers = match state {
+            Some(state) => self.on_event.entry((state, event)).or_default(),
+            None => self.any_on_event.entry(event).or_default(),
+        };
+
+        handlers.push(Labelled::new(Box::new(func)));
     }
 
     pub(crate) fn add_enter_handler(
         &mut self,
         state: TState,
-        func: impl Fn(&mut TModel) + 'static + Sync + Send,
+        func: impl Fn(&TransitionCtx<TState, TEvent>, &mut TModel) -> HandlerResult
+        + 'static
+        + Sync
+        + Send,
     ) {
         match self.on_enter.get_mut(&state) {
             Some(vec) => {
-                vec.push(Box::new(func));
+                vec.push(Labelled::new(Box::new(func)));
             }
             None => {
-                self.on_enter.insert(state, vec![Box::new(func)]);
+                self.on_enter
+                    .insert(state, vec![Labelled::new(Box::new(func))]);
             }
         }
     }
@@ -91,20 +208,366 @@ where
     pub(crate) fn add_leave_handler(
         &mut self,
         state: TState,
-        func: impl Fn(&mut TModel) + 'static + Sync + Send,
+        func: impl Fn(&TransitionCtx<TState, TEvent>, &mut TModel) -> HandlerResult
+        + 'static
+        + Sync
+        + Send,
     ) {
         match self.on_leave.get_mut(&state) {
             Some(vec) => {
-                vec.push(Box::new(func));
+                vec.push(Labelled::new(Box::new(func)));
             }
             None => {
-                self.on_leave.insert(state, vec![Box::new(func)]);
+                self.on_leave
+                    .insert(state, vec![Labelled::new(Box::new(func))]);
             }
         }
     }
 
-    pub(crate) fn add_transition(&mut self, on: TEvent, from: TState, to: TState) {
-        self.transitions.insert((from, on), to);
+    /// Add a transition from the given state, or from every state if `from` is `None`
+    pub(crate) fn add_transition(
+        &mut self,
+        on: TEvent,
+        from: Option<TState>,
+        to: TState,
+        guard: Option<Box<GuardFn<TModel>>>,
+    ) {
+        let to = Transition {
+            to,
+            guard: guard.map(Labelled::new),
+        };
+        match from {
+            Some(from) => {
+                self.transitions.insert((from, on), to);
+            }
+            None => {
+                self.any_transitions.insert(on, to);
+            }
+        }
+    }
+
+    /// Label the handler or guard added last at the given place
+    pub(crate) fn label(&mut self, handler: &HandlerRef<TState, TEvent>, label: String) {
+        let slot = match handler {
+            HandlerRef::Enter(state) => self
+                .on_enter
+                .get_mut(state)
+                .and_then(|v| v.last_mut())
+                .map(|h| &mut h.label),
+            HandlerRef::Leave(state) => self
+                .on_leave
+                .get_mut(state)
+                .and_then(|v| v.last_mut())
+                .map(|h| &mut h.label),
+            HandlerRef::Event(state, event) => match state {
+                Some(state) => self.on_event.get_mut(&(state.clone(), event.clone())),
+                None => self.any_on_event.get_mut(event),
+            }
+            .and_then(|v| v.last_mut())
+            .map(|h| &mut h.label),
+            HandlerRef::Guard(state, event) => match state {
+                Some(state) => self.transitions.get_mut(&(state.clone(), event.clone())),
+                None => self.any_transitions.get_mut(event),
+            }
+            .and_then(|t| t.guard.as_mut())
+            .map(|g| &mut g.label),
+            HandlerRef::Unhandled(state) => match state {
+                Some(state) => self.on_unhandled.get_mut(state),
+                None => Some(&mut self.any_on_unhandled),
+            }
+            .and_then(|v| v.last_mut())
+            .map(|h| &mut h.label),
+        };
+
+        if let Some(slot) = slot {
+            *slot = Some(label);
+        }
+    }
+
+    /// Add an error transition from the given state, or from every state if `from` is `None`
+    pub(crate) fn add_error_transition(&mut self, from: Option<TState>, to: TState) {
+        match from {
+            Some(from) => {
+                self.error_transitions.insert(from, to);
+            }
+            None => {
+                self.any_error_transition = Some(to);
+            }
+        }
+    }
+
+    /// Add a handler for events the given state doesn't handle, or for every state if `state` is
+    /// `None`
+    pub(crate) fn add_unhandled_handler(
+        &mut self,
+        state: Option<TState>,
+        func: impl Fn(&TState, &TEvent, &mut TModel) + 'static + Sync + Send,
+    ) {
+        let handlers = match state {
+            Some(state) => self.on_unhandled.entry(state).or_default(),
+            None => &mut self.any_on_unhandled,
+        };
+
+        handlers.push(Labelled::new(Box::new(func)));
+    }
+
+    pub(crate) fn set_catch_panics(&mut self, catch_panics: bool) {
+        self.catch_panics = catch_panics;
+    }
+
+    pub(crate) fn catches_panics(&self) -> bool {
+        self.catch_panics
+    }
+
+    pub(crate) fn add_failure_handler(
+        &mut self,
+        func: impl Fn(&HandlerError<TState, TEvent>) + 'static + Sync + Send,
+    ) {
+        self.on_failure.push(Box::new(func));
+    }
+
+    /// Call the given function with the source state, target state and event after every
+    /// transition, including those to an error state
+    pub(crate) fn add_transition_listener(
+        &mut self,
+        func: impl Fn(&TState, &TState, &Option<TEvent>) + 'static + Sync + Send,
+    ) {
+        self.transition_listeners.push(Box::new(func));
+    }
+
+    /// Call the given function with the initial state once `start` has entered it
+    #[cfg(feature = "loader")]
+    pub(crate) fn add_start_listener(&mut self, func: impl Fn(&TState) + 'static + Sync + Send) {
+        self.start_listeners.push(Box::new(func));
+    }
+
+    /// Handle the event in the given state without running anything
+    #[cfg(feature = "scxml")]
+    pub(crate) fn add_ignored_event(&mut self, state: TState, event: TEvent) {
+        self.on_event.entry((state, event)).or_default();
+    }
+
+    /// Mark the state as final, so that it's exported as an SCXML `<final>` state
+    #[cfg(feature = "scxml")]
+    pub(crate) fn add_final_state(&mut self, state: TState) {
+        self.final_states.insert(state);
+    }
+
+    pub(crate) fn is_final(&self, state: &TState) -> bool {
+        self.final_states.contains(state)
+    }
+
+    /// The state the machine starts in
+    pub fn initial_state(&self) -> &TState {
+        &self.initial_state
+    }
+
+    /// Every state the machine's definition mentions, in no particular order
+    pub fn states(&self) -> Vec<&TState> {
+        let mut seen = HashSet::new();
+
+        let states = [&self.initial_state]
+            .into_iter()
+            .chain(self.on_enter.keys())
+            .chain(self.on_leave.keys())
+            .chain(self.on_unhandled.keys())
+            .chain(self.on_event.keys().map(|(state, _)| state))
+            .chain(self.transitions.keys().map(|(state, _)| state))
+            .chain(self.transitions.values().map(|transition| &transition.to))
+            .chain(
+                self.any_transitions
+                    .values()
+                    .map(|transition| &transition.to),
+            )
+            .chain(self.error_transitions.keys())
+            .chain(self.error_transitions.values())
+            .chain(self.any_error_transition.iter())
+            .chain(self.final_states.iter());
+
+        states.filter(|state| seen.insert(*state)).collect()
+    }
+
+    /// Every event the machine's definition mentions, in no particular order
+    pub fn events(&self) -> Vec<&TEvent> {
+        let mut seen = HashSet::new();
+
+        let events = self
+            .on_event
+            .keys()
+            .chain(self.transitions.keys())
+            .map(|(_, event)| event)
+            .chain(self.any_on_event.keys())
+            .chain(self.any_transitions.keys());
+
+        events.filter(|event| seen.insert(*event)).collect()
+    }
+
+    /// Every transition as `(from, event, to)`, ignoring guards. Transitions added with
+    /// `in_any_state` are listed for each state that doesn't define the event itself.
+    pub fn transitions(&self) -> impl Iterator<Item = (&TState, &TEvent, &TState)> {
+        self.states().into_iter().flat_map(move |state| {
+            self.handled_events(state)
+                .into_iter()
+                .filter_map(move |event| {
+                    self.transition_target(state, event)
+                        .map(|to| (state, event, to))
+                })
+        })
+    }
+
+    /// Every event the given state handles, either itself or through `in_any_state`
+    pub fn handled_events(&self, state: &TState) -> Vec<&TEvent> {
+        let mut seen = HashSet::new();
+
+        let events = self
+            .on_event
+            .keys()
+            .chain(self.transitions.keys())
+            .filter(|(from, _)| from == state)
+            .map(|(_, event)| event)
+            .chain(self.any_on_event.keys())
+            .chain(self.any_transitions.keys());
+
+        events.filter(|event| seen.insert(*event)).collect()
+    }
+
+    /// The handlers and transition for an event in the given state, using the definitions for
+    /// any state if the state doesn't define the event itself
+    fn definition(&self, state: &TState, event: &TEvent) -> Definition<'_, TState, TModel> {
+        let key = (state.clone(), event.clone());
+        if self.on_event.contains_key(&key) || self.transitions.contains_key(&key) {
+            (self.on_event.get(&key), self.transitions.get(&key))
+        } else {
+            (
+                self.any_on_event.get(event),
+                self.any_transitions.get(event),
+            )
+        }
+    }
+
+    /// The state the given event moves to from the given state, ignoring guards
+    pub fn transition_target(&self, state: &TState, event: &TEvent) -> Option<&TState> {
+        let (_, transition) = self.definition(state, event);
+        transition.map(|transition| &transition.to)
+    }
+
+    /// The names of the handlers that run for an event in the given state, in order of definition.
+    /// Handlers that weren't named are `None`.
+    pub fn event_handler_names(&self, state: &TState, event: &TEvent) -> Vec<Option<&str>> {
+        let (handlers, _) = self.definition(state, event);
+        labels(handlers)
+    }
+
+    /// The name of the guard for an event in the given state, or `None` if it has no guard
+    pub fn guard_name(&self, state: &TState, event: &TEvent) -> Option<Option<&str>> {
+        let (_, transition) = self.definition(state, event);
+        transition
+            .and_then(|transition| transition.guard.as_ref())
+            .map(|guard| guard.label.as_deref())
+    }
+
+    /// The names of the given state's enter handlers, in order of definition
+    pub fn enter_handler_names(&self, state: &TState) -> Vec<Option<&str>> {
+        labels(self.on_enter.get(state))
+    }
+
+    /// The names of the given state's leave handlers, in order of definition
+    pub fn leave_handler_names(&self, state: &TState) -> Vec<Option<&str>> {
+        labels(self.on_leave.get(state))
+    }
+
+    /// The names of the handlers that run for events the given state doesn't handle, in order of
+    /// definition. A state with none of its own uses those given in `in_any_state`.
+    pub fn unhandled_handler_names(&self, state: &TState) -> Vec<Option<&str>> {
+        match self.on_unhandled.get(state) {
+            Some(handlers) => labels(Some(handlers)),
+            None => labels(Some(&self.any_on_unhandled)),
+        }
+    }
+
+    /// The state a failed handler in the given state moves to
+    pub fn error_target(&self, state: &TState) -> Option<&TState> {
+        self.error_transitions
+            .get(state)
+            .or(self.any_error_transition.as_ref())
+    }
+
+    /// Whether firing the event in the current state would do anything. A guarded transition
+    /// without event handlers counts only if its guard passes. With event handlers the guard isn't
+    /// checked, since `fire` only checks it once they have run, and they run either way.
+    pub fn can_fire(&self, event: &TEvent) -> bool {
+        let (handlers, transition) = self.definition(&self.current_state, event);
+        if handlers.is_some() {
+            return true;
+        }
+
+        match transition.map(|transition| transition.guard.as_ref()) {
+            Some(Some(guard)) => {
+                run_handler(self.catch_panics, || Ok((guard.func)(&self.model))).unwrap_or(false)
+            }
+            Some(None) => true,
+            None => false,
+        }
+    }
+
+    /// Report what firing the event would do without running any handlers, or `None` if nothing
+    /// would run at all. Guards aren't checked, so a guarded transition is reported along with
+    /// its guard.
+    pub fn peek(&self, event: &TEvent) -> Option<PlannedTransition<'_, TState>> {
+        let (handlers, transition) = self.definition(&self.current_state, event);
+        if handlers.is_none() && transition.is_none() {
+            let unhandled_handlers = self.unhandled_handler_names(&self.current_state);
+            if unhandled_handlers.is_empty() {
+                return None;
+            }
+
+            return Some(PlannedTransition {
+                to: None,
+                guard: None,
+                event_handlers: Vec::new(),
+                leave_handlers: Vec::new(),
+                enter_handlers: Vec::new(),
+                unhandled_handlers,
+            });
+        }
+
+        let to = transition.map(|transition| &transition.to);
+
+        Some(PlannedTransition {
+            to,
+            guard: transition
+                .and_then(|transition| transition.guard.as_ref())
+                .map(|guard| guard.label.as_deref()),
+            event_handlers: labels(handlers),
+            leave_handlers: match to {
+                Some(_) => self.leave_handler_names(&self.current_state),
+                None => Vec::new(),
+            },
+            enter_handlers: match to {
+                Some(to) => self.enter_handler_names(to),
+                None => Vec::new(),
+            },
+            unhandled_handlers: Vec::new(),
+        })
+    }
+
+    /// Every event `can_fire` allows in the current state, in no particular order
+    pub fn permitted_events(&self) -> Vec<&TEvent> {
+        self.handled_events(&self.current_state)
+            .into_iter()
+            .filter(|event| self.can_fire(event))
+            .collect()
+    }
+
+    /// Move to the state without running any handlers, for setting up tests
+    #[cfg(feature = "testing")]
+    pub(crate) fn set_state(&mut self, state: TState) {
+        self.current_state = state;
+    }
+
+    /// Whether `start` has been called
+    pub fn is_running(&self) -> bool {
+        self.running
     }
 
     pub fn current_state(&self) -> &TState {
@@ -119,51 +582,360 @@ where
         &mut self.model
     }
 
-    pub fn start(&mut self) {
+    pub fn start(&mut self) -> Result<(), HandlerError<TState, TEvent>> {
         if self.running {
-            return;
+            return Ok(());
         }
 
-        self.running = true;
+        let ctx = TransitionCtx {
+            from: None,
+            to: Some(self.current_state.clone()),
+            event: None,
+        };
 
         if let Some(actions) = self.on_enter.get(&(self.current_state)) {
-            for action in actions.iter() {
-                action(&mut self.model);
+            for (index, action) in actions.iter().enumerate() {
+                let result =
+                    run_handler(self.catch_panics, || (action.func)(&ctx, &mut self.model));
+                if let Err(cause) = result {
+                    let error = self.fail(HandlerKind::Enter, index, cause, None);
+                    // A machine that went on to enter its error state is running after all
+                    self.running = self.error_target(&error.state).is_some();
+                    return Err(error);
+                }
             }
         }
+
+        // Only a machine that has entered its state counts as running
+        self.running = true;
+        for listener in self.start_listeners.iter() {
+            listener(&self.current_state);
+        }
+
+        Ok(())
     }
 
-    pub fn fire(&mut self, event: TEvent) {
+    /// Run the current state's `on_leave` handlers, with no state to go `to`, and stop the machine,
+    /// so that `start` enters its state again. If a handler fails, the machine keeps running and
+    /// the failure is returned.
+    pub fn stop(&mut self) -> Result<(), HandlerError<TState, TEvent>> {
+        if !self.running {
+            return Ok(());
+        }
+
+        let ctx = TransitionCtx {
+            from: Some(self.current_state.clone()),
+            to: None,
+            event: None,
+        };
+
+        if let Some(actions) = self.on_leave.get(&(self.current_state)) {
+            for (index, action) in actions.iter().enumerate() {
+                let result =
+                    run_handler(self.catch_panics, || (action.func)(&ctx, &mut self.model));
+                if let Err(cause) = result {
+                    return Err(self.fail(HandlerKind::Leave, index, cause, None));
+                }
+            }
+        }
+
+        self.running = false;
+
+        Ok(())
+    }
+
+    /// Stop the machine and move it back to its initial state. It has to be started again.
+    pub fn reset(&mut self) -> Result<(), HandlerError<TState, TEvent>> {
+        self.reset_with(|_| {})
+    }
+
+    /// Like `reset`, but also resets the model
+    pub fn reset_with(
+        &mut self,
+        reset_model: impl FnOnce(&mut TModel),
+    ) -> Result<(), HandlerError<TState, TEvent>> {
+        self.stop()?;
+
+        reset_model(&mut self.model);
+        if self.current_state != self.initial_state {
+            let from = std::mem::replace(&mut self.current_state, self.initial_state.clone());
+            self.notify(&from, &None);
+        }
+
+        Ok(())
+    }
+
+    /// Fire an event, running its handlers and any transition it causes. If a handler fails, the
+    /// machine stays in its current state (or moves to the state given by `on_error().goto()`)
+    /// and the failure is returned.
+    pub fn fire(&mut self, event: TEvent) -> Result<(), HandlerError<TState, TEvent>> {
+        self.handle(event).map(|_| ())
+    }
+
+    /// Like `fire`, but reports what the event did
+    pub(crate) fn handle(
+        &mut self,
+        event: TEvent,
+    ) -> Result<Handled<TState>, HandlerError<TState, TEvent>> {
         if !self.running {
             panic!("State machine is not running");
         }
 
+        // A state that defines an event itself hides the definitions for any state
+        let key = (self.current_state.clone(), event);
+        let (handlers, transition) =
+            if self.on_event.contains_key(&key) || self.transitions.contains_key(&key) {
+                (self.on_event.get(&key), self.transitions.get(&key))
+            } else {
+                (
+                    self.any_on_event.get(&key.1),
+                    self.any_transitions.get(&key.1),
+                )
+            };
+        let event = key.1;
+
+        if handlers.is_none() && transition.is_none() {
+            return self.unhandled(event).map(|_| Handled::Unhandled);
+        }
+
         // Handle event and update state
-        if let Some(handlers) = self.on_event.get(&(self.current_state, event)) {
-            for handler in handlers.iter() {
-                handler(&mut self.model);
+        if let Some(handlers) = handlers {
+            for (index, handler) in handlers.iter().enumerate() {
+                let result = run_handler(self.catch_panics, || (handler.func)(&mut self.model));
+                if let Err(cause) = result {
+                    return Err(self.fail(HandlerKind::Event, index, cause, Some(event.clone())));
+                }
             }
         }
 
         // If a transition happens, handle on-leave and on-enter
-        if let Some(state) = self.transitions.get(&(self.current_state, event)) {
-            self.goto(*state);
+        if let Some(transition) = transition {
+            let allowed = match &transition.guard {
+                Some(guard) => run_handler(self.catch_panics, || Ok((guard.func)(&self.model))),
+                None => Ok(true),
+            };
+            let to = transition.to.clone();
+
+            match allowed {
+                Ok(true) => {
+                    let from = self.current_state.clone();
+                    self.goto(to, Some(event))?;
+                    return Ok(Handled::Transitioned { from });
+                }
+                Ok(false) => {}
+                Err(cause) => return Err(self.fail(HandlerKind::Guard, 0, cause, Some(event))),
+            }
         }
+
+        Ok(Handled::Stayed)
     }
 
-    pub(crate) fn goto(&mut self, state: TState) {
+    /// Run the unhandled event handlers of the current state, or the ones for any state if it has
+    /// none of its own
+    fn unhandled(&mut self, event: TEvent) -> Result<(), HandlerError<TState, TEvent>> {
+        let state = self.current_state.clone();
+        let handlers = match self.on_unhandled.get(&state) {
+            Some(handlers) => handlers,
+            None => &self.any_on_unhandled,
+        };
+
+        for (index, handler) in handlers.iter().enumerate() {
+            let result = run_handler(self.catch_panics, || {
+                (handler.func)(&state, &event, &mut self.model);
+                Ok(())
+            });
+            if let Err(cause) = result {
+                return Err(self.fail(HandlerKind::Unhandled, index, cause, Some(event)));
+            }
+        }
+
+        Ok(())
+    }
+
+    pub(crate) fn goto(
+        &mut self,
+        state: TState,
+        event: Option<TEvent>,
+    ) -> Result<(), HandlerError<TState, TEvent>> {
+        let ctx = TransitionCtx {
+            from: Some(self.current_state.clone()),
+            to: Some(state.clone()),
+            event,
+        };
+
         if let Some(actions) = self.on_leave.get(&(self.current_state)) {
-            for action in actions.iter() {
-                action(&mut self.model);
+            for (index, action) in actions.iter().enumerate() {
+                let result =
+                    run_handler(self.catch_panics, || (action.func)(&ctx, &mut self.model));
+                if let Err(cause) = result {
+                    return Err(self.fail(HandlerKind::Leave, index, cause, ctx.event.clone()));
+                }
+            }
+        }
+
+        // The state only changes once every handler has succeeded
+        if let Some(actions) = self.on_enter.get(&state) {
+            for (index, action) in actions.iter().enumerate() {
+                let result =
+                    run_handler(self.catch_panics, || (action.func)(&ctx, &mut self.model));
+                if let Err(cause) = result {
+                    return Err(self.fail(HandlerKind::Enter, index, cause, ctx.event.clone()));
+                }
             }
         }
 
         self.current_state = state;
+        self.notify(ctx.from.as_ref().unwrap(), &ctx.event);
 
-        if let Some(actions) = self.on_enter.get(&(self.current_state)) {
-            for action in actions.iter() {
-                action(&mut self.model);
+        Ok(())
+    }
+
+    fn notify(&self, from: &TState, event: &Option<TEvent>) {
+        for listener in self.transition_listeners.iter() {
+            listener(from, &self.current_state, event);
+        }
+    }
+
+    /// Build the error for a failed handler, moving to the error state if one was given with
+    /// `on_error().goto()` and reporting it to `on_failure`. Failures while entering the error
+    /// state are discarded.
+    pub(crate) fn fail(
+        &mut self,
+        kind: HandlerKind,
+        index: usize,
+        cause: BoxError,
+        event: Option<TEvent>,
+    ) -> HandlerError<TState, TEvent> {
+        let state = self.current_state.clone();
+
+        let error_state = match self.error_transitions.get(&state) {
+            Some(error_state) => Some(error_state.clone()),
+            None => self.any_error_transition.clone(),
+        };
+
+        if let Some(error_state) = error_state {
+            let ctx = TransitionCtx {
+                from: Some(state.clone()),
+                to: Some(error_state.clone()),
+                event: event.clone(),
+            };
+
+            self.current_state = error_state;
+
+            if let Some(actions) = self.on_enter.get(&self.current_state) {
+                for action in actions.iter() {
+                    if run_handler(self.catch_panics, || (action.func)(&ctx, &mut self.model))
+                        .is_err()
+                    {
+                        break;
+                    }
+                }
             }
+
+            self.notify(&state, &event);
         }
+
+        let error = HandlerError {
+            state,
+            event,
+            kind,
+            index,
+            cause,
+        };
+
+        // A failure handler that panics has nowhere to report to, so with `catch_panics` it's
+        // ignored rather than poisoning the machine
+        for handler in self.on_failure.iter() {
+            let _ = run_handler(self.catch_panics, || {
+                handler(&error);
+                Ok(())
+            });
+        }
+
+        error
+    }
+}
+
+/// Lists every state with the names of its handlers, without needing `Debug` on the model
+impl<TState, TModel, TEvent> Debug for PassiveStateMachine<TState, TModel, TEvent>
+where
+    TState: Eq + Hash + Clone + Debug,
+    TEvent: Eq + Hash + Clone + Debug,
+{
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let states = DebugWith(|f: &mut Formatter<'_>| {
+            // Sorted like the exporters, so the output doesn't change from run to run
+            let mut states = self.states();
+            states.sort_by_cached_key(|state| format!("{state:?}"));
+
+            let states = states.into_iter().map(|state| {
+                let events = DebugWith(move |f: &mut Formatter<'_>| {
+                    let mut events = self.handled_events(state);
+                    events.sort_by_cached_key(|event| format!("{event:?}"));
+
+                    let events = events.into_iter().map(|event| {
+                        let definition = DebugWith(move |f: &mut Formatter<'_>| {
+                            f.debug_struct("Event")
+                                .field("handlers", &self.event_handler_names(state, event))
+                                .field("guard", &self.guard_name(state, event))
+                                .field("goto", &self.transition_target(state, event))
+                                .finish()
+                        });
+                        (event, definition)
+                    });
+                    f.debug_map().entries(events).finish()
+                });
+                let definition = DebugWith(move |f: &mut Formatter<'_>| {
+                    f.debug_struct("State")
+                        .field("enter", &self.enter_handler_names(state))
+                        .field("leave", &self.leave_handler_names(state))
+                        .field("events", &events)
+                        .field("unhandled", &self.unhandled_handler_names(state))
+                        .field("on_error", &self.error_target(state))
+                        .finish()
+                });
+                (state, definition)
+            });
+            f.debug_map().entries(states).finish()
+        });
+
+        f.debug_struct("PassiveStateMachine")
+            .field("current_state", &self.current_state)
+            .field("running", &self.running)
+            .field("states", &states)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Formats with a closure, for building nested `Debug` output
+struct DebugWith<F>(F);
+
+impl<F: Fn(&mut Formatter<'_>) -> fmt::Result> Debug for DebugWith<F> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        (self.0)(f)
+    }
+}
+
+fn labels<F: ?Sized>(handlers: Option<&Vec<Labelled<F>>>) -> Vec<Option<&str>> {
+    handlers
+        .into_iter()
+        .flatten()
+        .map(|handler| handler.label.as_deref())
+        .collect()
+}
+
+/// Run a handler, turning a panic into an error if `catch_panics` is set. The model may be left
+/// half-updated by the panicking handler.
+pub(crate) fn run_handler<R>(
+    catch_panics: bool,
+    func: impl FnOnce() -> Result<R, BoxError>,
+) -> Result<R, BoxError> {
+    if !catch_panics {
+        return func();
+    }
+
+    match panic::catch_unwind(AssertUnwindSafe(func)) {
+        Ok(result) => result,
+        Err(payload) => Err(Box::new(HandlerPanic::from_payload(payload))),
     }
 }
@@ -0,0 +1,235 @@
+// MIT License
+//
+// Copyright (c) 2024 Wes Kelly
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Draw machines as [PlantUML](https://plantuml.com/state-diagram) state diagrams.
+//!
+//! Handlers and guards are shown by the names given with `label()` or the `_named` builder
+//! methods, and as `...` when they have none.
+
+use crate::passive::PassiveStateMachine;
+use std::collections::{HashMap, HashSet};
+use std::fmt::{Display, Write};
+use std::hash::Hash;
+
+const UNLABELLED: &str = "...";
+
+impl<TState, TModel, TEvent> PassiveStateMachine<TState, TModel, TEvent>
+where
+    TState: Eq + Hash + Clone + Display,
+    TEvent: Eq + Hash + Clone + Display,
+{
+    /// Describe the machine's states and transitions as a PlantUML state diagram. States and
+    /// events are sorted by name so the output is stable.
+    pub fn to_plantuml(&self) -> String {
+        let mut uml = String::new();
+
+        writeln!(uml, "@startuml").unwrap();
+
+        let mut states = self.states();
+        states.sort_by_cached_key(|state| state.to_string());
+
+        // PlantUML only accepts identifiers as state names, so other names get an alias that
+        // isn't already taken by a state's own name
+        let mut taken: HashSet<String> = states
+            .iter()
+            .map(|state| state.to_string())
+            .filter(|name| is_identifier(name))
+            .collect();
+        let mut names = HashMap::new();
+        for state in states.iter() {
+            let name = state.to_string();
+            let id = if is_identifier(&name) {
+                name
+            } else {
+                let alias = (0..)
+                    .map(|n| format!("state{n}"))
+                    .find(|alias| !taken.contains(alias))
+                    .unwrap();
+                taken.insert(alias.clone());
+                writeln!(uml, r#"state "{}" as {alias}"#, name.replace('"', "'")).unwrap();
+                alias
+            };
+            names.insert(*state, id);
+        }
+
+        writeln!(uml, "[*] --> {}", names[self.initial_state()]).unwrap();
+
+        for state in states {
+            let id = &names[state];
+
+            let entry = self.enter_handler_names(state);
+            if !entry.is_empty() {
+                writeln!(uml, "state {id} : entry / {}", join(&entry)).unwrap();
+            }
+
+            let exit = self.leave_handler_names(state);
+            if !exit.is_empty() {
+                writeln!(uml, "state {id} : exit / {}", join(&exit)).unwrap();
+            }
+
+            let mut events = self.handled_events(state);
+            events.sort_by_cached_key(|event| event.to_string());
+
+            for event in events {
+                let mut label = event.to_string();
+                if let Some(guard) = self.guard_name(state, event) {
+                    write!(label, " [{}]", guard.unwrap_or(UNLABELLED)).unwrap();
+                }
+                let handlers = self.event_handler_names(state, event);
+                if !handlers.is_empty() {
+                    write!(label, " / {}", join(&handlers)).unwrap();
+                }
+
+                match self.transition_target(state, event) {
+                    Some(target) => writeln!(uml, "{id} --> {} : {label}", names[target]),
+                    None => writeln!(uml, "state {id} : {label}"),
+                }
+                .unwrap();
+            }
+
+            if let Some(target) = self.error_target(state) {
+                writeln!(uml, "{id} --> {} : error", names[target]).unwrap();
+            }
+        }
+
+        writeln!(uml, "@enduml").unwrap();
+
+        uml
+    }
+}
+
+fn is_identifier(name: &str) -> bool {
+    !name.is_empty()
+        && !name.starts_with(|c: char| c.is_ascii_digit())
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+fn join(labels: &[Option<&str>]) -> String {
+    labels
+        .iter()
+        .map(|label| label.unwrap_or(UNLABELLED))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::builder::StateMachineBuilder;
+    use Events::*;
+    use States::*;
+    use std::fmt::{Display, Formatter};
+
+    #[derive(Eq, PartialEq, Copy, Clone, Hash, Debug)]
+    enum States {
+        Locked,
+        Unlocked,
+        Broken,
+    }
+
+    #[derive(Eq, PartialEq, Copy, Clone, Hash, Debug)]
+    enum Events {
+        Coin,
+        Push,
+    }
+
+    impl Display for States {
+        fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+            write!(f, "{self:?}")
+        }
+    }
+
+    impl Display for Events {
+        fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+            write!(f, "{self:?}")
+        }
+    }
+
+    #[test]
+    fn test_to_plantuml() {
+        let machine = StateMachineBuilder::create(Locked, 0u32)
+            .on_enter(|| {})
+            .label("lock")
+            .on_mut(Coin, |coins| *coins += 1)
+            .label("count")
+            .goto_if(Unlocked, |coins| *coins > 0)
+            .label("paid")
+            .on(Push, || {})
+            .in_state(Unlocked)
+            .on_leave(|| {})
+            .on(Push, || {})
+            .goto(Locked)
+            .in_any_state()
+            .on_error()
+            .goto(Broken)
+            .build_passive();
+
+        let expected = "\
+@startuml
+[*] --> Locked
+Broken --> Broken : error
+state Locked : entry / lock
+Locked --> Unlocked : Coin [paid] / count
+state Locked : Push / ...
+Locked --> Broken : error
+state Unlocked : exit / ...
+Unlocked --> Locked : Push / ...
+Unlocked --> Broken : error
+@enduml
+";
+        assert_eq!(machine.to_plantuml(), expected);
+    }
+
+    #[test]
+    fn test_to_plantuml_aliases_names() {
+        let machine = StateMachineBuilder::create("Door closed".to_string(), ())
+            .on("open".to_string(), || {})
+            .goto("Door open".to_string())
+            .build_passive();
+
+        let expected = "\
+@startuml
+state \"Door closed\" as state0
+state \"Door open\" as state1
+[*] --> state0
+state0 --> state1 : open / ...
+@enduml
+";
+        assert_eq!(machine.to_plantuml(), expected);
+    }
+
+    #[test]
+    fn test_to_plantuml_aliases_avoid_state_names() {
+        let machine = StateMachineBuilder::create("A b".to_string(), ())
+            .on("go".to_string(), || {})
+            .goto("state0".to_string())
+            .build_passive();
+
+        let expected = "\
+@startuml
+state \"A b\" as state1
+[*] --> state1
+state1 --> state0 : go / ...
+@enduml
+";
+        assert_eq!(machine.to_plantuml(), expected);
+    }
+}
@@ -0,0 +1,377 @@
+// MIT License
+//
+// Copyright (c) 2024 Wes Kelly
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! The queue between an active machine and the threads that fire events into it.
+//!
+//! The queue is unbounded unless `bounded_queue` is given to the builder. Only events count
+//! towards the capacity; commands such as `start` and `stop` are always queued.
+//!
+//! Events are handled highest priority first, and in the order they were fired within a priority.
+//! `start` is always handled before any event, and `stop` after every event queued before it,
+//! unless the builder was given `urgent_stop`. `stop_after_drain` and `stop_now` close the queue,
+//! so no more events can be fired.
+//!
+//! A paused machine leaves events queued until it resumes, or rejects them if the builder was
+//! given `while_paused(WhilePaused::Reject)`.
+
+use crate::error::FireError;
+use std::collections::VecDeque;
+use std::sync::{Arc, Condvar, Mutex};
+
+/// What firing an event into a full queue does
+#[derive(Debug, Eq, PartialEq, Copy, Clone, Hash)]
+pub enum Overflow {
+    /// Wait for the machine to make room. Manual machines return `FireError::Full` instead.
+    Block,
+    /// Discard the event being fired
+    DropNewest,
+    /// Discard the oldest of the lowest priority queued events to make room. If every queued event
+    /// has a higher priority than the one being fired, that one is discarded instead.
+    DropOldest,
+    /// Return the event to the caller in a `FireError::Full`
+    Error,
+}
+
+/// How soon a fired event is handled, relative to the others in the queue
+#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Copy, Clone, Hash, Default)]
+pub enum Priority {
+    Low,
+    #[default]
+    Normal,
+    High,
+}
+
+// Commands that jump ahead of every event share a lane above `High`
+const URGENT: u8 = Priority::High as u8 + 1;
+
+/// What firing an event into a paused machine does
+#[derive(Debug, Eq, PartialEq, Copy, Clone, Hash, Default)]
+pub enum WhilePaused {
+    /// Queue the event to be handled once the machine resumes
+    #[default]
+    Queue,
+    /// Return the event to the caller in a `FireError::Paused`
+    Reject,
+}
+
+/// A snapshot of an active machine's queue
+#[derive(Debug, Eq, PartialEq, Copy, Clone, Hash)]
+pub struct QueueStats {
+    /// The number of events and commands waiting to be handled
+    pub depth: usize,
+    /// The most events the queue holds, or `None` if it's unbounded
+    pub capacity: Option<usize>,
+    /// The deepest the queue has been
+    pub max_depth: usize,
+    /// The number of events discarded because the queue was full
+    pub dropped: u64,
+}
+
+/// How an active machine's queue is set up, from `bounded_queue` on the builder
+#[derive(Copy, Clone)]
+pub(crate) struct QueueConfig {
+    pub(crate) capacity: Option<usize>,
+    pub(crate) overflow: Overflow,
+    pub(crate) urgent_stop: bool,
+    pub(crate) while_paused: WhilePaused,
+}
+
+impl Default for QueueConfig {
+    fn default() -> Self {
+        Self {
+            capacity: None,
+            overflow: Overflow::Block,
+            urgent_stop: false,
+            while_paused: WhilePaused::Queue,
+        }
+    }
+}
+
+pub(crate) enum TryRecvError {
+    Empty,
+    Disconnected,
+}
+
+struct Entry<T> {
+    item: T,
+    // Events count towards the capacity, commands don't
+    bounded: bool,
+    lane: u8,
+}
+
+struct QueueState<T> {
+    // Urgent commands come first. Events are sorted by lane, highest first, but never overtake a
+    // command that was queued before them.
+    items: VecDeque<Entry<T>>,
+    bounded: usize,
+    config: QueueConfig,
+    senders: usize,
+    receiver_alive: bool,
+    closed: bool,
+    paused: bool,
+    max_depth: usize,
+    dropped: u64,
+}
+
+struct Queue<T> {
+    state: Mutex<QueueState<T>>,
+    space: Condvar,
+}
+
+pub(crate) struct Sender<T>(Arc<Queue<T>>);
+
+pub(crate) struct Receiver<T>(Arc<Queue<T>>);
+
+pub(crate) fn queue<T>(config: QueueConfig) -> (Sender<T>, Receiver<T>) {
+    let queue = Arc::new(Queue {
+        state: Mutex::new(QueueState {
+            items: VecDeque::new(),
+            bounded: 0,
+            config,
+            senders: 1,
+            receiver_alive: true,
+            closed: false,
+            paused: false,
+            max_depth: 0,
+            dropped: 0,
+        }),
+        space: Condvar::new(),
+    });
+
+    (Sender(Arc::clone(&queue)), Receiver(queue))
+}
+
+impl<T> Sender<T> {
+    /// Queue an event, applying the overflow policy if the queue is full. Returns the event that
+    /// was discarded to make room, if any.
+    pub(crate) fn send(&self, item: T, priority: Priority) -> Result<Option<T>, FireError<T>> {
+        let lane = priority as u8;
+        let mut state = self.0.state.lock().unwrap();
+
+        loop {
+            if !state.receiver_alive || state.closed {
+                return Err(FireError::Stopped(item));
+            }
+
+            if state.paused && state.config.while_paused == WhilePaused::Reject {
+                return Err(FireError::Paused(item));
+            }
+
+            let full = state
+                .config
+                .capacity
+                .is_some_and(|capacity| state.bounded >= capacity);
+            if !full {
+                break;
+            }
+
+            match state.config.overflow {
+                Overflow::Block => state = self.0.space.wait(state).unwrap(),
+                Overflow::DropNewest => {
+                    state.dropped += 1;
+                    return Err(FireError::Full(item));
+                }
+                Overflow::Error => return Err(FireError::Full(item)),
+                Overflow::DropOldest => {
+                    // Events with a higher priority than this one are never dropped for it
+                    let droppable = |entry: &Entry<T>| entry.bounded && entry.lane <= lane;
+                    let lowest = state.items.iter().filter(|entry| droppable(entry));
+                    let lowest = lowest.map(|entry| entry.lane).min();
+                    let oldest = state
+                        .items
+                        .iter()
+                        .position(|entry| droppable(entry) && Some(entry.lane) == lowest);
+
+                    state.dropped += 1;
+                    let Some(dropped) = oldest.and_then(|oldest| state.remove(oldest)) else {
+                        return Err(FireError::Full(item));
+                    };
+                    state.push(item, true, lane);
+                    return Ok(Some(dropped));
+                }
+            }
+        }
+
+        state.push(item, true, lane);
+        Ok(None)
+    }
+
+    /// Queue a command, which doesn't count towards the capacity. Urgent commands are handled
+    /// before any event, others after everything already queued, whatever its priority.
+    pub(crate) fn send_command(&self, item: T, urgent: bool) -> Result<(), FireError<T>> {
+        let mut state = self.0.state.lock().unwrap();
+        if !state.receiver_alive {
+            return Err(FireError::Stopped(item));
+        }
+
+        let lane = if urgent { URGENT } else { Priority::Low as u8 };
+        state.push(item, false, lane);
+        Ok(())
+    }
+
+    /// Stop accepting events and queue a final command, either behind everything already queued
+    /// or, if `discard` is set, ahead of it. Returns the events that were discarded.
+    pub(crate) fn close(&self, command: T, discard: bool) -> Vec<T> {
+        let mut state = self.0.state.lock().unwrap();
+        state.closed = true;
+
+        let mut discarded = Vec::new();
+        if discard {
+            let items = std::mem::take(&mut state.items);
+            for entry in items {
+                if entry.bounded {
+                    discarded.push(entry.item);
+                } else {
+                    state.items.push_back(entry);
+                }
+            }
+            state.bounded = 0;
+        }
+
+        let lane = if discard { URGENT } else { Priority::Low as u8 };
+        if state.receiver_alive {
+            state.push(command, false, lane);
+        }
+
+        // Wake blocked senders so they see the queue has closed
+        self.0.space.notify_all();
+        discarded
+    }
+
+    /// Mark the machine as paused, for `WhilePaused::Reject`. Blocked senders are woken so they
+    /// can be rejected.
+    pub(crate) fn set_paused(&self, paused: bool) {
+        self.0.state.lock().unwrap().paused = paused;
+        self.0.space.notify_all();
+    }
+
+    pub(crate) fn is_paused(&self) -> bool {
+        self.0.state.lock().unwrap().paused
+    }
+
+    pub(crate) fn overflow(&self) -> Overflow {
+        self.0.state.lock().unwrap().config.overflow
+    }
+
+    pub(crate) fn urgent_stop(&self) -> bool {
+        self.0.state.lock().unwrap().config.urgent_stop
+    }
+
+    pub(crate) fn stats(&self) -> QueueStats {
+        let state = self.0.state.lock().unwrap();
+        QueueStats {
+            depth: state.items.len(),
+            capacity: state.config.capacity,
+            max_depth: state.max_depth,
+            dropped: state.dropped,
+        }
+    }
+}
+
+impl<T> Receiver<T> {
+    pub(crate) fn try_recv(&self) -> Result<T, TryRecvError> {
+        let mut state = self.0.state.lock().unwrap();
+        match state.remove(0) {
+            Some(item) => {
+                self.0.space.notify_one();
+                Ok(item)
+            }
+            None if state.senders == 0 => Err(TryRecvError::Disconnected),
+            None => Err(TryRecvError::Empty),
+        }
+    }
+
+    /// Like `try_recv`, but only takes urgent commands, which are all at the front. Other
+    /// commands stay behind the events queued before them.
+    pub(crate) fn try_recv_command(&self) -> Result<T, TryRecvError> {
+        let mut state = self.0.state.lock().unwrap();
+        let urgent = state
+            .items
+            .front()
+            .is_some_and(|entry| entry.lane == URGENT);
+        match urgent.then(|| state.remove(0)).flatten() {
+            Some(item) => Ok(item),
+            None if state.senders == 0 => Err(TryRecvError::Disconnected),
+            None => Err(TryRecvError::Empty),
+        }
+    }
+}
+
+impl<T> QueueState<T> {
+    fn push(&mut self, item: T, bounded: bool, lane: u8) {
+        let index = if lane == URGENT {
+            // Go behind the urgent commands already queued, which are all at the front
+            self.items.partition_point(|entry| entry.lane == URGENT)
+        } else if !bounded {
+            self.items.len()
+        } else {
+            // Go behind every command and everything in the same or a higher lane
+            self.items
+                .iter()
+                .rposition(|entry| !entry.bounded || entry.lane >= lane)
+                .map_or(0, |index| index + 1)
+        };
+        self.items.insert(
+            index,
+            Entry {
+                item,
+                bounded,
+                lane,
+            },
+        );
+        self.bounded += bounded as usize;
+        self.max_depth = self.max_depth.max(self.items.len());
+    }
+
+    fn remove(&mut self, index: usize) -> Option<T> {
+        let entry = self.items.remove(index)?;
+        self.bounded -= entry.bounded as usize;
+        Some(entry.item)
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        self.0.state.lock().unwrap().senders += 1;
+        Self(Arc::clone(&self.0))
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        // The lock can only be poisoned by a panic while queueing, which leaves nothing to update
+        if let Ok(mut state) = self.0.state.lock() {
+            state.senders -= 1;
+        }
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        if let Ok(mut state) = self.0.state.lock() {
+            state.receiver_alive = false;
+        }
+
+        // Wake blocked senders so they see the machine has stopped
+        self.0.space.notify_all();
+    }
+}
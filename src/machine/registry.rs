@@ -0,0 +1,95 @@
+// MIT License
+//
+// Copyright (c) 2024 Wes Kelly
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use crate::error::BoxError;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+pub(crate) type NamedHandler<TModel> =
+    Arc<dyn Fn(&mut TModel) -> Result<(), BoxError> + Sync + Send>;
+pub(crate) type NamedGuard<TModel> = Arc<dyn Fn(&TModel) -> bool + Sync + Send>;
+
+/// Handlers and guards that machine definitions loaded at runtime can refer to by name
+pub struct HandlerRegistry<TModel> {
+    handlers: HashMap<String, NamedHandler<TModel>>,
+    guards: HashMap<String, NamedGuard<TModel>>,
+}
+
+impl<TModel> Default for HandlerRegistry<TModel> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<TModel> HandlerRegistry<TModel> {
+    pub fn new() -> Self {
+        Self {
+            handlers: HashMap::new(),
+            guards: HashMap::new(),
+        }
+    }
+
+    /// Make a handler available under the given name
+    pub fn register(
+        self,
+        name: impl Into<String>,
+        func: impl Fn(&mut TModel) + 'static + Sync + Send,
+    ) -> Self {
+        self.register_fallible(name, move |model: &mut TModel| {
+            func(model);
+            Ok::<(), BoxError>(())
+        })
+    }
+
+    /// Make a handler that can fail available under the given name
+    pub fn register_fallible<E: Into<BoxError>>(
+        self,
+        name: impl Into<String>,
+        func: impl Fn(&mut TModel) -> Result<(), E> + 'static + Sync + Send,
+    ) -> Self {
+        let mut registry = self;
+        registry.handlers.insert(
+            name.into(),
+            Arc::new(move |model| func(model).map_err(Into::into)),
+        );
+        registry
+    }
+
+    /// Make a guard available under the given name
+    pub fn register_guard(
+        self,
+        name: impl Into<String>,
+        func: impl Fn(&TModel) -> bool + 'static + Sync + Send,
+    ) -> Self {
+        let mut registry = self;
+        registry.guards.insert(name.into(), Arc::new(func));
+        registry
+    }
+
+    pub(crate) fn handler(&self, name: &str) -> Option<NamedHandler<TModel>> {
+        self.handlers.get(name).cloned()
+    }
+
+    pub(crate) fn guard(&self, name: &str) -> Option<NamedGuard<TModel>> {
+        self.guards.get(name).cloned()
+    }
+}
@@ -0,0 +1,416 @@
+// MIT License
+//
+// Copyright (c) 2024 Wes Kelly
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Exchange machines with tools that use [W3C SCXML](https://www.w3.org/TR/scxml/).
+//!
+//! Only flat machines are supported, so compound and parallel states can't be imported. Handlers
+//! and guards are bound by name from a `HandlerRegistry`; handlers are written as
+//! `<fluent:action name="..."/>` elements in `onentry`, `onexit` and `transition`, and guards as
+//! the `cond` of a transition. Handler failures are routed by transitions on `error.execution`.
+//!
+//! Handlers and guards without a name are exported as `fluent:unnamed`, which import refuses
+//! rather than leaving them out. Final states are imported as states with no transitions, and
+//! exported as `<final>` again.
+
+use crate::passive::PassiveStateMachine;
+use std::fmt::{Display, Write};
+use std::hash::Hash;
+
+#[cfg(feature = "scxml")]
+mod import;
+
+#[cfg(feature = "scxml")]
+pub use import::ScxmlError;
+
+const SCXML_NAMESPACE: &str = "http://www.w3.org/2005/07/scxml";
+const FLUENT_NAMESPACE: &str = "https://github.com/Xerxes004/fluent-fsm";
+const ERROR_EVENT: &str = "error.execution";
+// Written in place of the name of a handler or guard that has none, which can't be bound on import
+const UNNAMED: &str = "fluent:unnamed";
+
+impl<TState, TModel, TEvent> PassiveStateMachine<TState, TModel, TEvent>
+where
+    TState: Eq + Hash + Clone + Display,
+    TEvent: Eq + Hash + Clone + Display,
+{
+    /// Describe the machine's states and transitions as an SCXML document. States and events are
+    /// sorted by name so the output is stable.
+    pub fn to_scxml(&self) -> String {
+        let mut scxml = String::new();
+
+        writeln!(scxml, r#"<?xml version="1.0" encoding="UTF-8"?>"#).unwrap();
+        writeln!(
+            scxml,
+            r#"<scxml xmlns="{SCXML_NAMESPACE}" xmlns:fluent="{FLUENT_NAMESPACE}" version="1.0" initial="{}">"#,
+            escape(&self.initial_state().to_string())
+        )
+        .unwrap();
+
+        let mut states = self.states();
+        states.sort_by_cached_key(|state| state.to_string());
+
+        for state in states {
+            let element = if self.is_final(state) {
+                "final"
+            } else {
+                "state"
+            };
+            writeln!(
+                scxml,
+                r#"  <{element} id="{}">"#,
+                escape(&state.to_string())
+            )
+            .unwrap();
+
+            write_actions(&mut scxml, "onentry", &self.enter_handler_names(state));
+            write_actions(&mut scxml, "onexit", &self.leave_handler_names(state));
+
+            let mut events = self.handled_events(state);
+            events.sort_by_cached_key(|event| event.to_string());
+
+            for event in events {
+                let mut attributes = format!(r#"event="{}""#, escape(&event.to_string()));
+                if let Some(target) = self.transition_target(state, event) {
+                    write!(attributes, r#" target="{}""#, escape(&target.to_string())).unwrap();
+                }
+                if let Some(guard) = self.guard_name(state, event) {
+                    let guard = guard.unwrap_or(UNNAMED);
+                    write!(attributes, r#" cond="{}""#, escape(guard)).unwrap();
+                }
+
+                let handlers = self.event_handler_names(state, event);
+                if !handlers.is_empty() {
+                    writeln!(scxml, "    <transition {attributes}>").unwrap();
+                    write_action_list(&mut scxml, "      ", &handlers);
+                    writeln!(scxml, "    </transition>").unwrap();
+                } else {
+                    writeln!(scxml, "    <transition {attributes}/>").unwrap();
+                }
+            }
+
+            if let Some(target) = self.error_target(state) {
+                writeln!(
+                    scxml,
+                    r#"    <transition event="{ERROR_EVENT}" target="{}"/>"#,
+                    escape(&target.to_string())
+                )
+                .unwrap();
+            }
+
+            writeln!(scxml, "  </{element}>").unwrap();
+        }
+
+        writeln!(scxml, "</scxml>").unwrap();
+
+        scxml
+    }
+}
+
+/// Write the handlers in an `onentry` or `onexit` block, skipping it if there are none
+fn write_actions(scxml: &mut String, block: &str, handlers: &[Option<&str>]) {
+    if !handlers.is_empty() {
+        writeln!(scxml, "    <{block}>").unwrap();
+        write_action_list(scxml, "      ", handlers);
+        writeln!(scxml, "    </{block}>").unwrap();
+    }
+}
+
+/// Write a `fluent:action` for each handler
+fn write_action_list(scxml: &mut String, indent: &str, handlers: &[Option<&str>]) {
+    for name in handlers.iter() {
+        let name = name.unwrap_or(UNNAMED);
+        writeln!(scxml, r#"{indent}<fluent:action name="{}"/>"#, escape(name)).unwrap();
+    }
+}
+
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::builder::StateMachineBuilder;
+    use crate::passive::PassiveStateMachine;
+    use Events::*;
+    use States::*;
+    use std::fmt::{Display, Formatter};
+    use std::str::FromStr;
+
+    #[derive(Eq, PartialEq, Copy, Clone, Hash, Debug)]
+    enum States {
+        Locked,
+        Unlocked,
+        Broken,
+    }
+
+    #[derive(Eq, PartialEq, Copy, Clone, Hash, Debug)]
+    enum Events {
+        Coin,
+        Push,
+    }
+
+    impl Display for States {
+        fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+            write!(f, "{self:?}")
+        }
+    }
+
+    impl Display for Events {
+        fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+            write!(f, "{self:?}")
+        }
+    }
+
+    impl FromStr for States {
+        type Err = ();
+
+        fn from_str(s: &str) -> Result<Self, Self::Err> {
+            [Locked, Unlocked, Broken]
+                .into_iter()
+                .find(|state| state.to_string() == s)
+                .ok_or(())
+        }
+    }
+
+    impl FromStr for Events {
+        type Err = ();
+
+        fn from_str(s: &str) -> Result<Self, Self::Err> {
+            [Coin, Push]
+                .into_iter()
+                .find(|event| event.to_string() == s)
+                .ok_or(())
+        }
+    }
+
+    fn turnstile() -> PassiveStateMachine<States, u32, Events> {
+        StateMachineBuilder::create(Locked, 0)
+            .on_mut(Coin, |coins| *coins += 1)
+            .goto(Unlocked)
+            .on(Push, || {})
+            .in_state(Unlocked)
+            .on(Push, || {})
+            .goto(Locked)
+            .in_any_state()
+            .on_error()
+            .goto(Broken)
+            .build_passive()
+    }
+
+    const TURNSTILE: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<scxml xmlns="http://www.w3.org/2005/07/scxml" xmlns:fluent="https://github.com/Xerxes004/fluent-fsm" version="1.0" initial="Locked">
+  <state id="Broken">
+    <transition event="error.execution" target="Broken"/>
+  </state>
+  <state id="Locked">
+    <transition event="Coin" target="Unlocked">
+      <fluent:action name="fluent:unnamed"/>
+    </transition>
+    <transition event="Push">
+      <fluent:action name="fluent:unnamed"/>
+    </transition>
+    <transition event="error.execution" target="Broken"/>
+  </state>
+  <state id="Unlocked">
+    <transition event="Push" target="Locked">
+      <fluent:action name="fluent:unnamed"/>
+    </transition>
+    <transition event="error.execution" target="Broken"/>
+  </state>
+</scxml>
+"#;
+
+    #[test]
+    fn test_to_scxml() {
+        assert_eq!(turnstile().to_scxml(), TURNSTILE);
+    }
+
+    #[cfg(feature = "scxml")]
+    mod import {
+        use super::*;
+        use crate::registry::HandlerRegistry;
+        use crate::scxml::ScxmlError;
+
+        fn registry() -> HandlerRegistry<u32> {
+            HandlerRegistry::new()
+                .register("add_coin", |coins: &mut u32| *coins += 1)
+                .register("reset", |coins: &mut u32| *coins = 0)
+                .register_guard("paid", |coins: &u32| *coins >= 2)
+        }
+
+        #[test]
+        fn test_from_scxml() {
+            let scxml = r#"
+                <scxml xmlns="http://www.w3.org/2005/07/scxml"
+                       xmlns:fluent="https://github.com/Xerxes004/fluent-fsm"
+                       version="1.0" initial="Locked">
+                  <state id="Locked">
+                    <onentry><fluent:action name="reset"/></onentry>
+                    <transition event="Coin" cond="paid" target="Unlocked">
+                      <fluent:action name="add_coin"/>
+                    </transition>
+                  </state>
+                  <final id="Unlocked"/>
+                </scxml>
+            "#;
+
+            let mut machine: PassiveStateMachine<States, u32, Events> =
+                PassiveStateMachine::from_scxml(scxml, &registry(), 5).unwrap();
+
+            machine.start().unwrap();
+            assert_eq!(*machine.model(), 0);
+
+            machine.fire(Coin).unwrap();
+            assert_eq!(*machine.current_state(), Locked);
+
+            machine.fire(Coin).unwrap();
+            assert_eq!(*machine.current_state(), Unlocked);
+            assert_eq!(*machine.model(), 2);
+        }
+
+        #[test]
+        fn test_scxml_round_trip() {
+            let scxml = r#"<?xml version="1.0" encoding="UTF-8"?>
+<scxml xmlns="http://www.w3.org/2005/07/scxml" xmlns:fluent="https://github.com/Xerxes004/fluent-fsm" version="1.0" initial="Locked">
+  <final id="Broken">
+    <onentry>
+      <fluent:action name="reset"/>
+    </onentry>
+  </final>
+  <state id="Locked">
+    <transition event="Coin" target="Unlocked">
+      <fluent:action name="add_coin"/>
+    </transition>
+    <transition event="Push"/>
+    <transition event="error.execution" target="Broken"/>
+  </state>
+  <state id="Unlocked">
+    <transition event="Push" target="Locked" cond="paid"/>
+    <transition event="error.execution" target="Broken"/>
+  </state>
+</scxml>
+"#;
+
+            let machine: PassiveStateMachine<States, u32, Events> =
+                PassiveStateMachine::from_scxml(scxml, &registry(), 0).unwrap();
+
+            assert_eq!(machine.to_scxml(), scxml);
+        }
+
+        #[test]
+        fn test_unnamed_handler_is_not_dropped() {
+            let imported =
+                PassiveStateMachine::<States, u32, Events>::from_scxml(TURNSTILE, &registry(), 0);
+            assert!(matches!(
+                imported,
+                Err(ScxmlError::UnnamedHandler(state)) if state == "Locked"
+            ));
+        }
+
+        #[test]
+        fn test_scxml_round_trip_keeps_names() {
+            let scxml = r#"<?xml version="1.0" encoding="UTF-8"?>
+<scxml xmlns="http://www.w3.org/2005/07/scxml" xmlns:fluent="https://github.com/Xerxes004/fluent-fsm" version="1.0" initial="Locked">
+  <state id="Locked">
+    <onentry>
+      <fluent:action name="reset"/>
+    </onentry>
+    <transition event="Coin" target="Unlocked" cond="paid">
+      <fluent:action name="add_coin"/>
+    </transition>
+  </state>
+  <state id="Unlocked">
+  </state>
+</scxml>
+"#;
+
+            let machine: PassiveStateMachine<States, u32, Events> =
+                PassiveStateMachine::from_scxml(scxml, &registry(), 0).unwrap();
+
+            assert_eq!(machine.enter_handler_names(&Locked), [Some("reset")]);
+            assert_eq!(machine.to_scxml(), scxml);
+        }
+
+        #[test]
+        fn test_unnamed_guard_is_not_dropped() {
+            let machine = StateMachineBuilder::create(Locked, 0u32)
+                .on_named(Coin, "add_coin", |coins| *coins += 1)
+                .goto_if(Unlocked, |coins| *coins > 0)
+                .build_passive();
+
+            let scxml = machine.to_scxml();
+            assert!(
+                scxml.contains(
+                    r#"<transition event="Coin" target="Unlocked" cond="fluent:unnamed">"#
+                )
+            );
+
+            let imported =
+                PassiveStateMachine::<States, u32, Events>::from_scxml(&scxml, &registry(), 0);
+            assert!(matches!(
+                imported,
+                Err(ScxmlError::UnnamedGuard { state, event }) if state == "Locked" && event == "Coin"
+            ));
+        }
+
+        #[test]
+        fn test_unsupported_scxml() {
+            let import = |scxml: &str| {
+                PassiveStateMachine::<States, u32, Events>::from_scxml(scxml, &registry(), 0)
+                    .map(|_| ())
+            };
+
+            assert!(matches!(
+                import(r#"<scxml><parallel id="Locked"/></scxml>"#),
+                Err(ScxmlError::Unsupported(_))
+            ));
+            assert!(matches!(
+                import(r#"<scxml><state id="Locked"><state id="Unlocked"/></state></scxml>"#),
+                Err(ScxmlError::Unsupported(_))
+            ));
+            assert!(matches!(
+                import(r#"<scxml><state id="Open"/></scxml>"#),
+                Err(ScxmlError::UnknownState(name)) if name == "Open"
+            ));
+            assert!(matches!(
+                import(r#"<scxml><state id="Locked"><onentry><fluent:action name="x"/></onentry></state></scxml>"#),
+                Err(ScxmlError::UnknownHandler(name)) if name == "x"
+            ));
+            assert!(matches!(
+                import(r#"<scxml><final id="Locked"><transition event="Coin"/></final></scxml>"#),
+                Err(ScxmlError::Unsupported(_))
+            ));
+            assert!(matches!(import("<scxml>"), Err(ScxmlError::Xml(_))));
+        }
+    }
+}
@@ -0,0 +1,365 @@
+// MIT License
+//
+// Copyright (c) 2024 Wes Kelly
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Import of SCXML documents, behind the `scxml` feature.
+
+use super::{ERROR_EVENT, UNNAMED};
+use crate::passive::{HandlerRef, PassiveStateMachine};
+use crate::registry::{HandlerRegistry, NamedHandler};
+use quick_xml::XmlVersion;
+use quick_xml::events::{BytesStart, Event};
+use std::collections::HashSet;
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::hash::Hash;
+use std::str::FromStr;
+
+#[derive(Debug)]
+pub enum ScxmlError {
+    /// The document isn't well-formed XML
+    Xml(String),
+    /// The document uses SCXML that flat machines can't represent
+    Unsupported(String),
+    MissingAttribute {
+        element: String,
+        attribute: String,
+    },
+    UnknownState(String),
+    UnknownEvent(String),
+    UnknownHandler(String),
+    UnknownGuard(String),
+    /// A transition's guard had no name when it was exported, so it can't be bound
+    UnnamedGuard {
+        state: String,
+        event: String,
+    },
+    /// A handler in the state had no name when it was exported, so it can't be bound
+    UnnamedHandler(String),
+}
+
+struct Element {
+    name: String,
+    attributes: Vec<(String, String)>,
+    children: Vec<Element>,
+}
+
+impl Element {
+    fn read(start: &BytesStart) -> Result<Self, ScxmlError> {
+        let mut attributes = Vec::new();
+        for attribute in start.attributes() {
+            let attribute = attribute.map_err(|e| ScxmlError::Xml(e.to_string()))?;
+            let value = attribute
+                .normalized_value(XmlVersion::Implicit1_0)
+                .map_err(|e| ScxmlError::Xml(e.to_string()))?;
+            let key = attribute.key.local_name();
+            let key: &str = key.as_ref();
+            attributes.push((key.to_string(), value.into_owned()));
+        }
+
+        let name = start.local_name();
+        let name: &str = name.as_ref();
+
+        Ok(Self {
+            name: name.to_string(),
+            attributes,
+            children: Vec::new(),
+        })
+    }
+
+    fn attribute(&self, name: &str) -> Option<&str> {
+        self.attributes
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    fn required(&self, name: &str) -> Result<&str, ScxmlError> {
+        self.attribute(name)
+            .ok_or_else(|| ScxmlError::MissingAttribute {
+                element: self.name.clone(),
+                attribute: name.to_string(),
+            })
+    }
+}
+
+fn parse(text: &str) -> Result<Element, ScxmlError> {
+    let mut reader = quick_xml::Reader::from_str(text);
+    let mut open: Vec<Element> = Vec::new();
+
+    loop {
+        let closed = match reader.read_event() {
+            Ok(Event::Start(start)) => {
+                open.push(Element::read(&start)?);
+                continue;
+            }
+            Ok(Event::Empty(start)) => Element::read(&start)?,
+            Ok(Event::End(_)) => open.pop().expect("the reader checks end tags"),
+            Ok(Event::Eof) => return Err(ScxmlError::Xml("unexpected end of document".into())),
+            Ok(_) => continue,
+            Err(e) => return Err(ScxmlError::Xml(e.to_string())),
+        };
+
+        match open.last_mut() {
+            Some(parent) => parent.children.push(closed),
+            None => return Ok(closed),
+        }
+    }
+}
+
+impl<TState, TModel, TEvent> PassiveStateMachine<TState, TModel, TEvent>
+where
+    TState: Eq + Hash + Clone + FromStr,
+    TModel: 'static,
+    TEvent: Eq + Hash + Clone + FromStr,
+{
+    /// Create a passive state machine from an SCXML document, binding handlers and guards from
+    /// the registry. Final states become states with no transitions.
+    pub fn from_scxml(
+        text: &str,
+        registry: &HandlerRegistry<TModel>,
+        model: TModel,
+    ) -> Result<Self, ScxmlError> {
+        let root = parse(text)?;
+        if root.name != "scxml" {
+            return Err(ScxmlError::Unsupported(format!(
+                "root element <{}>",
+                root.name
+            )));
+        }
+
+        let mut states = Vec::new();
+        for child in root.children.iter() {
+            match child.name.as_str() {
+                "state" | "final" => states.push(child),
+                other => return Err(ScxmlError::Unsupported(format!("<{other}>"))),
+            }
+        }
+
+        let initial = match root.attribute("initial") {
+            Some(initial) if initial.split_whitespace().count() > 1 => {
+                return Err(ScxmlError::Unsupported(
+                    "more than one initial state".into(),
+                ));
+            }
+            Some(initial) => initial,
+            None => match states.first() {
+                Some(state) => state.required("id")?,
+                None => return Err(ScxmlError::Unsupported("a document without states".into())),
+            },
+        };
+
+        let mut machine = PassiveStateMachine::new(parse_state(initial)?, model);
+        let mut defined = HashSet::new();
+
+        for element in states {
+            let id = element.required("id")?;
+            let state: TState = parse_state(id)?;
+            if element.name == "final" {
+                machine.add_final_state(state.clone());
+            }
+
+            for child in element.children.iter() {
+                match child.name.as_str() {
+                    "onentry" => {
+                        for (name, handler) in actions(id, child, registry)? {
+                            machine
+                                .add_enter_handler(state.clone(), move |_, model| handler(model));
+                            machine.label(&HandlerRef::Enter(state.clone()), name.to_string());
+                        }
+                    }
+                    "onexit" => {
+                        for (name, handler) in actions(id, child, registry)? {
+                            machine
+                                .add_leave_handler(state.clone(), move |_, model| handler(model));
+                            machine.label(&HandlerRef::Leave(state.clone()), name.to_string());
+                        }
+                    }
+                    "transition" if element.name == "final" => {
+                        return Err(ScxmlError::Unsupported(format!(
+                            "a transition out of final state `{id}`"
+                        )));
+                    }
+                    "transition" => {
+                        let target = match child.attribute("target") {
+                            Some(target) if target.split_whitespace().count() > 1 => {
+                                return Err(ScxmlError::Unsupported(
+                                    "a transition with more than one target".into(),
+                                ));
+                            }
+                            Some(target) => Some(parse_state::<TState>(target)?),
+                            None => None,
+                        };
+                        let guard = match child.attribute("cond") {
+                            Some(UNNAMED) => {
+                                return Err(ScxmlError::UnnamedGuard {
+                                    state: id.to_string(),
+                                    event: child.required("event")?.to_string(),
+                                });
+                            }
+                            Some(cond) => Some(
+                                registry
+                                    .guard(cond)
+                                    .ok_or_else(|| ScxmlError::UnknownGuard(cond.to_string()))?,
+                            ),
+                            None => None,
+                        };
+                        let handlers = actions(id, child, registry)?;
+
+                        let events = child.required("event")?.split_whitespace();
+                        for name in events {
+                            if name.contains('*') {
+                                return Err(ScxmlError::Unsupported(format!("event `{name}`")));
+                            }
+
+                            if !defined.insert((id, name)) {
+                                return Err(ScxmlError::Unsupported(format!(
+                                    "more than one transition on `{name}` from `{id}`"
+                                )));
+                            }
+
+                            if name == ERROR_EVENT {
+                                match (&target, &guard, handlers.is_empty()) {
+                                    (Some(target), None, true) => machine
+                                        .add_error_transition(Some(state.clone()), target.clone()),
+                                    _ => {
+                                        return Err(ScxmlError::Unsupported(format!(
+                                            "an `{ERROR_EVENT}` transition with a guard, handlers \
+                                             or no target"
+                                        )));
+                                    }
+                                }
+                                continue;
+                            }
+
+                            let event = TEvent::from_str(name)
+                                .map_err(|_| ScxmlError::UnknownEvent(name.to_string()))?;
+
+                            for (handler_name, handler) in handlers.iter() {
+                                let handler = handler.clone();
+                                machine.add_event_handler(
+                                    Some(state.clone()),
+                                    event.clone(),
+                                    move |model| handler(model),
+                                );
+                                machine.label(
+                                    &HandlerRef::Event(Some(state.clone()), event.clone()),
+                                    handler_name.to_string(),
+                                );
+                            }
+
+                            match (&target, &guard) {
+                                (Some(target), guard) => {
+                                    let guard = guard.clone().map(|guard| {
+                                        Box::new(move |model: &TModel| guard(model)) as _
+                                    });
+                                    machine.add_transition(
+                                        event.clone(),
+                                        Some(state.clone()),
+                                        target.clone(),
+                                        guard,
+                                    );
+                                    if let Some(cond) = child.attribute("cond") {
+                                        machine.label(
+                                            &HandlerRef::Guard(Some(state.clone()), event),
+                                            cond.to_string(),
+                                        );
+                                    }
+                                }
+                                (None, Some(_)) => {
+                                    return Err(ScxmlError::Unsupported(
+                                        "a guarded transition without a target".into(),
+                                    ));
+                                }
+                                (None, None) if handlers.is_empty() => {
+                                    // Swallow the event without doing anything
+                                    machine.add_ignored_event(state.clone(), event);
+                                }
+                                (None, None) => {}
+                            }
+                        }
+                    }
+                    "state" | "final" | "parallel" | "initial" | "history" => {
+                        return Err(ScxmlError::Unsupported(format!("compound state `{id}`")));
+                    }
+                    other => return Err(ScxmlError::Unsupported(format!("<{other}> in `{id}`"))),
+                }
+            }
+        }
+
+        Ok(machine)
+    }
+}
+
+fn actions<'a, TModel>(
+    state: &str,
+    element: &'a Element,
+    registry: &HandlerRegistry<TModel>,
+) -> Result<Vec<(&'a str, NamedHandler<TModel>)>, ScxmlError> {
+    let mut handlers = Vec::new();
+    for child in element.children.iter() {
+        if child.name != "action" {
+            return Err(ScxmlError::Unsupported(format!("<{}>", child.name)));
+        }
+
+        let name = child.required("name")?;
+        if name == UNNAMED {
+            return Err(ScxmlError::UnnamedHandler(state.to_string()));
+        }
+
+        let handler = registry
+            .handler(name)
+            .ok_or_else(|| ScxmlError::UnknownHandler(name.to_string()))?;
+        handlers.push((name, handler));
+    }
+    Ok(handlers)
+}
+
+fn parse_state<TState: FromStr>(name: &str) -> Result<TState, ScxmlError> {
+    TState::from_str(name).map_err(|_| ScxmlError::UnknownState(name.to_string()))
+}
+
+impl Display for ScxmlError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ScxmlError::Xml(message) => write!(f, "invalid XML: {message}"),
+            ScxmlError::Unsupported(what) => write!(f, "unsupported SCXML: {what}"),
+            ScxmlError::MissingAttribute { element, attribute } => {
+                write!(f, "<{element}> is missing the `{attribute}` attribute")
+            }
+            ScxmlError::UnknownState(name) => write!(f, "unknown state `{name}`"),
+            ScxmlError::UnknownEvent(name) => write!(f, "unknown event `{name}`"),
+            ScxmlError::UnknownHandler(name) => write!(f, "no handler registered as `{name}`"),
+            ScxmlError::UnknownGuard(name) => write!(f, "no guard registered as `{name}`"),
+            ScxmlError::UnnamedHandler(state) => {
+                write!(f, "a handler in `{state}` was exported without a name")
+            }
+            ScxmlError::UnnamedGuard { state, event } => {
+                write!(
+                    f,
+                    "the guard on `{event}` in `{state}` was exported without a name"
+                )
+            }
+        }
+    }
+}
+
+impl Error for ScxmlError {}
@@ -0,0 +1,549 @@
+// MIT License
+//
+// Copyright (c) 2024 Wes Kelly
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Helpers for testing machines.
+//!
+//! A `PropertyTest` fires random sequences of the machine's known events at fresh machines and
+//! checks invariants on the state and model after every step. When an invariant fails, the
+//! sequence is shrunk to a minimal one that still fails. Handler failures aren't failures by
+//! themselves; check the state or model in an invariant if they matter. Events need to be `Ord`,
+//! so that a seed gives the same sequences on every build.
+//!
+//! A `Scenario` scripts a single run with `given`/`when`/`then` steps, and panics with the path
+//! the machine took when an expectation isn't met.
+
+use crate::active::{ActiveStateMachine, Stepped};
+use crate::passive::PassiveStateMachine;
+use std::error::Error;
+use std::fmt::{Debug, Display, Formatter};
+use std::hash::Hash;
+
+type Factory<TState, TModel, TEvent> = Box<dyn Fn() -> PassiveStateMachine<TState, TModel, TEvent>>;
+type Invariant<TState, TModel> = (String, Box<dyn Fn(&TState, &TModel) -> bool>);
+
+/// Fires random sequences of events at a machine, checking invariants after every step
+pub struct PropertyTest<TState, TModel, TEvent>
+where
+    TState: Eq + Hash + Clone,
+    TEvent: Ord + Hash + Clone,
+{
+    factory: Factory<TState, TModel, TEvent>,
+    invariants: Vec<Invariant<TState, TModel>>,
+    runs: usize,
+    steps: usize,
+    seed: u64,
+}
+
+/// A minimal sequence of events that breaks an invariant
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Counterexample<TState, TEvent> {
+    /// The events fired after `start`, in order
+    pub events: Vec<TEvent>,
+    /// The state the machine was in when the invariant failed
+    pub state: TState,
+    /// The name of the invariant that failed
+    pub invariant: String,
+}
+
+impl<TState, TModel, TEvent> PropertyTest<TState, TModel, TEvent>
+where
+    TState: Eq + Hash + Clone,
+    TEvent: Ord + Hash + Clone,
+{
+    /// Create a property test for the machines the factory builds. Every run and every shrinking
+    /// step gets a fresh machine, so the factory should build the same machine each time.
+    pub fn new(
+        factory: impl Fn() -> PassiveStateMachine<TState, TModel, TEvent> + 'static,
+    ) -> Self {
+        Self {
+            factory: Box::new(factory),
+            invariants: Vec::new(),
+            runs: 100,
+            steps: 50,
+            seed: 0x5eed,
+        }
+    }
+
+    /// Check the given invariant once the machine starts and after every event
+    pub fn invariant(
+        self,
+        name: impl Into<String>,
+        check: impl Fn(&TState, &TModel) -> bool + 'static,
+    ) -> Self {
+        let mut test = self;
+        test.invariants.push((name.into(), Box::new(check)));
+        test
+    }
+
+    /// Set how many random sequences are tried, 100 by default
+    pub fn runs(self, runs: usize) -> Self {
+        Self { runs, ..self }
+    }
+
+    /// Set how many events each sequence has, 50 by default
+    pub fn steps(self, steps: usize) -> Self {
+        Self { steps, ..self }
+    }
+
+    /// Set the seed for generating sequences, so that a failure can be reproduced
+    pub fn seed(self, seed: u64) -> Self {
+        Self { seed, ..self }
+    }
+
+    /// Run the test, returning the smallest failing sequence found
+    pub fn check(&self) -> Result<(), Counterexample<TState, TEvent>> {
+        let mut events: Vec<TEvent> = (self.factory)().events().into_iter().cloned().collect();
+        if events.is_empty() {
+            return self.replay(&[]).map_or(Ok(()), Err);
+        }
+
+        // Event order in the definition isn't stable, so sort it for reproducible sequences
+        events.sort();
+
+        let mut rng = SplitMix64(self.seed);
+        for _ in 0..self.runs {
+            let sequence: Vec<TEvent> = (0..self.steps)
+                .map(|_| events[rng.below(events.len())].clone())
+                .collect();
+
+            if let Some(failure) = self.replay(&sequence) {
+                return Err(self.shrink(failure));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Fire the events at a fresh machine, stopping at the first broken invariant
+    fn replay(&self, events: &[TEvent]) -> Option<Counterexample<TState, TEvent>> {
+        let mut machine = (self.factory)();
+        let _ = machine.start();
+
+        let mut fired = Vec::with_capacity(events.len());
+        if let Some(failure) = self.violation(&machine, &fired) {
+            return Some(failure);
+        }
+
+        for event in events {
+            let _ = machine.fire(event.clone());
+            fired.push(event.clone());
+
+            if let Some(failure) = self.violation(&machine, &fired) {
+                return Some(failure);
+            }
+        }
+
+        None
+    }
+
+    fn violation(
+        &self,
+        machine: &PassiveStateMachine<TState, TModel, TEvent>,
+        fired: &[TEvent],
+    ) -> Option<Counterexample<TState, TEvent>> {
+        let state = machine.current_state();
+        self.invariants
+            .iter()
+            .find(|(_, check)| !check(state, machine.model()))
+            .map(|(name, _)| Counterexample {
+                events: fired.to_vec(),
+                state: state.clone(),
+                invariant: name.clone(),
+            })
+    }
+
+    /// Remove ever smaller runs of events for as long as the sequence keeps failing
+    fn shrink(&self, failure: Counterexample<TState, TEvent>) -> Counterexample<TState, TEvent> {
+        let mut smallest = failure;
+        let mut chunk = smallest.events.len() / 2;
+
+        while chunk > 0 {
+            let mut start = 0;
+            while start < smallest.events.len() {
+                let mut candidate = smallest.events.clone();
+                let end = (start + chunk).min(candidate.len());
+                candidate.drain(start..end);
+
+                match self.replay(&candidate) {
+                    Some(failure) => smallest = failure,
+                    None => start += chunk,
+                }
+            }
+
+            chunk /= 2;
+        }
+
+        smallest
+    }
+}
+
+impl<TState: Debug, TEvent: Debug> Display for Counterexample<TState, TEvent> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "invariant `{}` failed in state {:?} after events {:?}",
+            self.invariant, self.state, self.events
+        )
+    }
+}
+
+impl<TState: Debug, TEvent: Debug> Error for Counterexample<TState, TEvent> {}
+
+/// A machine that a `Scenario` can drive
+pub trait ScenarioMachine {
+    type State;
+    type Model;
+    type Event;
+
+    /// Start the machine, returning a description of the failure if it fails
+    fn start_machine(&mut self) -> Result<(), String>;
+
+    /// Fire the event and wait for it to be handled, returning a description of the failure if a
+    /// handler fails
+    fn fire_event(&mut self, event: Self::Event) -> Result<(), String>;
+
+    fn state(&self) -> Self::State;
+
+    /// Move the machine to the state without running any handlers
+    fn set_state(&mut self, state: Self::State);
+
+    fn check_model(&self, check: &dyn Fn(&Self::Model) -> bool) -> bool;
+
+    fn update_model(&mut self, update: &dyn Fn(&mut Self::Model));
+}
+
+impl<TState, TModel, TEvent> ScenarioMachine for PassiveStateMachine<TState, TModel, TEvent>
+where
+    TState: Eq + Hash + Clone,
+    TEvent: Eq + Hash + Clone,
+{
+    type State = TState;
+    type Model = TModel;
+    type Event = TEvent;
+
+    fn start_machine(&mut self) -> Result<(), String> {
+        self.start().map_err(|e| e.to_string())
+    }
+
+    fn fire_event(&mut self, event: TEvent) -> Result<(), String> {
+        self.fire(event).map_err(|e| e.to_string())
+    }
+
+    fn state(&self) -> TState {
+        self.current_state().clone()
+    }
+
+    fn set_state(&mut self, state: TState) {
+        PassiveStateMachine::set_state(self, state)
+    }
+
+    fn check_model(&self, check: &dyn Fn(&TModel) -> bool) -> bool {
+        check(self.model())
+    }
+
+    fn update_model(&mut self, update: &dyn Fn(&mut TModel)) {
+        update(self.model_mut())
+    }
+}
+
+/// Active machines can only be driven by a scenario if they were built with
+/// `build_active_manual`. Each step of the scenario handles exactly one command.
+impl<TState, TModel, TEvent> ScenarioMachine for ActiveStateMachine<TState, TModel, TEvent>
+where
+    TState: Eq + Hash + Clone + Sync + Send + 'static,
+    TModel: Sync + Send + 'static,
+    TEvent: Eq + Hash + Clone + Sync + Send + 'static,
+{
+    type State = TState;
+    type Model = TModel;
+    type Event = TEvent;
+
+    fn start_machine(&mut self) -> Result<(), String> {
+        self.start();
+        stepped(self.step_with_result())
+    }
+
+    fn fire_event(&mut self, event: TEvent) -> Result<(), String> {
+        self.fire(event).map_err(|e| e.to_string())?;
+        stepped(self.step_with_result())
+    }
+
+    fn state(&self) -> TState {
+        self.current_state()
+    }
+
+    fn set_state(&mut self, state: TState) {
+        self.with_machine(|machine| machine.set_state(state))
+    }
+
+    fn check_model(&self, check: &dyn Fn(&TModel) -> bool) -> bool {
+        self.read(|_, model| check(model))
+    }
+
+    fn update_model(&mut self, update: &dyn Fn(&mut TModel)) {
+        self.with_machine(|machine| update(machine.model_mut()))
+    }
+}
+
+fn stepped<TState, TEvent>(result: Stepped<TState, TEvent>) -> Result<(), String> {
+    match result.map_err(|e| e.to_string())? {
+        Some(result) => result.map_err(|e| e.to_string()),
+        None => Err("the machine has stopped".into()),
+    }
+}
+
+/// A scripted run of a machine. Each step happens as soon as it's called, and a `then` step that
+/// isn't met panics with the path the machine took.
+pub struct Scenario<TMachine: ScenarioMachine> {
+    machine: TMachine,
+    path: Vec<String>,
+}
+
+impl<TMachine> Scenario<TMachine>
+where
+    TMachine: ScenarioMachine,
+    TMachine::State: Eq + Debug,
+    TMachine::Event: Debug,
+{
+    /// Start the machine for a new scenario
+    pub fn new(machine: TMachine) -> Self {
+        let mut scenario = Self {
+            machine,
+            path: Vec::new(),
+        };
+
+        let result = scenario.machine.start_machine();
+        let step = format!("started in {:?}", scenario.machine.state());
+        scenario.record(step, result);
+        scenario
+    }
+
+    /// Move the machine to the given state without running any handlers
+    pub fn given_state(self, state: TMachine::State) -> Self {
+        let mut scenario = self;
+        scenario.path.push(format!("placed in {state:?}"));
+        scenario.machine.set_state(state);
+        scenario
+    }
+
+    /// Change the model before the next step
+    pub fn given_model(self, update: impl Fn(&mut TMachine::Model)) -> Self {
+        let mut scenario = self;
+        scenario.path.push("model updated".into());
+        scenario.machine.update_model(&update);
+        scenario
+    }
+
+    /// Fire an event. A handler failure is recorded in the path rather than failing the scenario.
+    pub fn when(self, event: TMachine::Event) -> Self {
+        let mut scenario = self;
+
+        let from = scenario.machine.state();
+        let step = format!("{event:?}: {from:?}");
+        let result = scenario.machine.fire_event(event);
+        let step = format!("{step} -> {:?}", scenario.machine.state());
+
+        scenario.record(step, result);
+        scenario
+    }
+
+    /// Expect the machine to be in the given state
+    #[track_caller]
+    pub fn then_state(self, state: TMachine::State) -> Self {
+        let current = self.machine.state();
+        if current != state {
+            self.fail(format!("expected state {state:?}, but was in {current:?}"));
+        }
+        self
+    }
+
+    /// Expect the check to pass on the model
+    #[track_caller]
+    pub fn then_model(self, check: impl Fn(&TMachine::Model) -> bool) -> Self {
+        if !self.machine.check_model(&check) {
+            self.fail("the model check failed".into());
+        }
+        self
+    }
+
+    /// The steps taken so far, such as `Coin: Locked -> Unlocked`
+    pub fn path(&self) -> &[String] {
+        &self.path
+    }
+
+    /// End the scenario, handing back the machine
+    pub fn into_machine(self) -> TMachine {
+        self.machine
+    }
+
+    fn record(&mut self, step: String, result: Result<(), String>) {
+        match result {
+            Ok(()) => self.path.push(step),
+            Err(e) => self.path.push(format!("{step} (failed: {e})")),
+        }
+    }
+
+    #[track_caller]
+    fn fail(&self, message: String) -> ! {
+        let mut report = format!("scenario failed: {message}\npath:");
+        for (number, step) in self.path.iter().enumerate() {
+            report.push_str(&format!("\n  {}. {step}", number + 1));
+        }
+        panic!("{report}")
+    }
+}
+
+/// A small, fast generator so that the crate doesn't need a dependency for random numbers
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// A number in `0..bound`
+    fn below(&mut self, bound: usize) -> usize {
+        (self.next() % bound as u64) as usize
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder::StateMachineBuilder;
+    use Events::*;
+    use States::*;
+
+    #[derive(Eq, PartialEq, Copy, Clone, Hash, Debug)]
+    enum States {
+        Locked,
+        Unlocked,
+    }
+
+    #[derive(Eq, PartialEq, Ord, PartialOrd, Copy, Clone, Hash, Debug)]
+    enum Events {
+        Coin,
+        Push,
+    }
+
+    // Counts the coins taken since the turnstile last let someone through
+    fn turnstile() -> PassiveStateMachine<States, u32, Events> {
+        StateMachineBuilder::create(Locked, 0)
+            .on_mut(Coin, |coins| *coins += 1)
+            .goto(Unlocked)
+            .in_state(Unlocked)
+            .on_mut(Coin, |coins| *coins += 1)
+            .on_mut(Push, |coins| *coins = 0)
+            .goto(Locked)
+            .build_passive()
+    }
+
+    #[test]
+    fn test_invariant_holds() {
+        let result = PropertyTest::new(turnstile)
+            .invariant("locked turnstiles hold no coins", |state, coins| {
+                *state == Unlocked || *coins == 0
+            })
+            .check();
+
+        assert_eq!(result, Ok(()));
+    }
+
+    #[test]
+    fn test_failure_is_shrunk() {
+        let failure = PropertyTest::new(turnstile)
+            .invariant("at most two coins", |_, coins| *coins <= 2)
+            .check()
+            .unwrap_err();
+
+        assert_eq!(failure.events, [Coin, Coin, Coin]);
+        assert_eq!(failure.state, Unlocked);
+        assert_eq!(failure.invariant, "at most two coins");
+    }
+
+    #[test]
+    fn test_scenario() {
+        let scenario = Scenario::new(turnstile())
+            .when(Coin)
+            .then_state(Unlocked)
+            .then_model(|coins| *coins == 1)
+            .when(Push)
+            .then_state(Locked)
+            .given_state(Unlocked)
+            .given_model(|coins| *coins = 5)
+            .when(Coin)
+            .then_model(|coins| *coins == 6);
+
+        assert_eq!(
+            scenario.path(),
+            [
+                "started in Locked",
+                "Coin: Locked -> Unlocked",
+                "Push: Unlocked -> Locked",
+                "placed in Unlocked",
+                "model updated",
+                "Coin: Unlocked -> Unlocked",
+            ]
+        );
+    }
+
+    #[test]
+    #[should_panic(
+        expected = "scenario failed: expected state Locked, but was in Unlocked\npath:\n  \
+                    1. started in Locked\n  2. Coin: Locked -> Unlocked"
+    )]
+    fn test_scenario_failure_report() {
+        Scenario::new(turnstile()).when(Coin).then_state(Locked);
+    }
+
+    #[test]
+    fn test_active_scenario() {
+        let machine = StateMachineBuilder::create(Locked, 0)
+            .on_mut(Coin, |coins| *coins += 1)
+            .goto(Unlocked)
+            .build_active_manual(|_, coins| (*coins >= 3).then_some(Locked));
+
+        Scenario::new(machine)
+            .given_model(|coins| *coins = 2)
+            .when(Coin)
+            .then_state(Unlocked)
+            .then_model(|coins| *coins == 3)
+            .into_machine()
+            .stop();
+    }
+
+    #[test]
+    fn test_invariant_checked_on_start() {
+        let failure = PropertyTest::new(turnstile)
+            .invariant("starts unlocked", |state, _| *state == Unlocked)
+            .seed(7)
+            .check()
+            .unwrap_err();
+
+        assert!(failure.events.is_empty());
+        assert_eq!(failure.state, Locked);
+    }
+}